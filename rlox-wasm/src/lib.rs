@@ -1,32 +1,67 @@
-use wasm_bindgen::prelude::wasm_bindgen;
+use serde::Serialize;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+
+// Offsets are in UTF-16 code units, matching editor positions
+#[derive(Serialize)]
+struct Diagnostic {
+    start_line: u32,
+    start_offset: u32,
+    end_line: u32,
+    end_offset: u32,
+    message: String,
+}
+
+#[derive(Serialize, Default)]
+struct Output {
+    messages: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Default)]
+struct CapturingReporter {
+    output: RefCell<Output>,
+}
+
+impl rlox::Reporter for CapturingReporter {
+    fn add_diagnostic(&self, location: &rlox::Region, message: &str) {
+        self.output.borrow_mut().diagnostics.push(Diagnostic {
+            start_line: location.start.line,
+            start_offset: location.start.utf16_offset,
+            end_line: location.end.line,
+            end_offset: location.end.utf16_offset,
+            message: message.to_string(),
+        });
+    }
+
+    fn add_message(&self, message: &str) {
+        self.output.borrow_mut().messages.push(message.to_string());
+    }
+
+    fn has_diagnostics(&self) -> bool {
+        !self.output.borrow().diagnostics.is_empty()
+    }
+}
+
+#[wasm_bindgen]
+pub fn interpret(source: &str) -> JsValue {
+    let reporter = CapturingReporter::default();
+    rlox::Vm::new(&reporter).interpret(source);
+    serde_wasm_bindgen::to_value(&reporter.output.into_inner()).unwrap_or(JsValue::NULL)
+}
 
 #[wasm_bindgen]
 pub fn example() {
     let mut chunk = rlox::Chunk::new();
     let reporter = rlox::DefaultReporter::new();
 
-    let constant = chunk.add_constant(rlox::Value::Number(1.2));
-    chunk.write_op_code(
-        rlox::OpCode::Constant,
-        rlox::Region::new_single_line(123, 0, 0),
-    );
-    chunk.write_byte(constant as u8, rlox::Region::new_single_line(123, 0, 0));
+    chunk.write_constant(rlox::Value::Number(1.2), rlox::Region::new_single_line(123, 0, 0));
 
-    let constant = chunk.add_constant(rlox::Value::Number(3.4));
-    chunk.write_op_code(
-        rlox::OpCode::Constant,
-        rlox::Region::new_single_line(124, 0, 0),
-    );
-    chunk.write_byte(constant as u8, rlox::Region::new_single_line(124, 0, 0));
+    chunk.write_constant(rlox::Value::Number(3.4), rlox::Region::new_single_line(124, 0, 0));
 
     chunk.write_op_code(rlox::OpCode::Add, rlox::Region::new_single_line(124, 0, 0));
 
-    let constant = chunk.add_constant(rlox::Value::Number(5.6));
-    chunk.write_op_code(
-        rlox::OpCode::Constant,
-        rlox::Region::new_single_line(124, 0, 0),
-    );
-    chunk.write_byte(constant as u8, rlox::Region::new_single_line(124, 0, 0));
+    chunk.write_constant(rlox::Value::Number(5.6), rlox::Region::new_single_line(124, 0, 0));
 
     chunk.write_op_code(
         rlox::OpCode::Divide,
@@ -36,6 +71,10 @@ pub fn example() {
         rlox::OpCode::Negate,
         rlox::Region::new_single_line(124, 0, 0),
     );
+    chunk.write_op_code(
+        rlox::OpCode::Print,
+        rlox::Region::new_single_line(124, 0, 0),
+    );
     chunk.write_op_code(
         rlox::OpCode::Return,
         rlox::Region::new_single_line(125, 0, 0),
@@ -43,6 +82,8 @@ pub fn example() {
     rlox::disassemble_chunk(&reporter, &chunk, "test chunk #1");
 
     let mut vm = rlox::Vm::new(&reporter);
+    vm.run_chunk(&chunk);
+
     println!("Interpreting...");
-    vm.interpret("");
+    vm.interpret("print -(1.2 + 3.4) / 5.6;");
 }
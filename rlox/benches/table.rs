@@ -0,0 +1,87 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::HashMap;
+
+const KEYS: u32 = 1_000;
+
+// Stands in for an interned string: equality by identity, hash computed once up front.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    id: u32,
+    hash: u32,
+}
+
+impl rlox::TableKey for Key {
+    fn hash_code(&self) -> u32 {
+        self.hash
+    }
+}
+
+fn keys() -> Vec<Key> {
+    (0..KEYS)
+        .map(|id| Key {
+            id,
+            hash: id.wrapping_mul(2654435761),
+        })
+        .collect()
+}
+
+fn filled_table(keys: &[Key]) -> rlox::Table<Key, f64> {
+    let mut table = rlox::Table::new();
+    keys.iter().for_each(|key| {
+        table.set(*key, key.id as f64);
+    });
+    table
+}
+
+fn filled_hash_map(keys: &[Key]) -> HashMap<Key, f64> {
+    keys.iter().map(|key| (*key, key.id as f64)).collect()
+}
+
+fn insert(c: &mut Criterion) {
+    let keys = keys();
+    let mut group = c.benchmark_group("insert");
+    group.bench_function("table", |b| b.iter(|| filled_table(black_box(&keys))));
+    group.bench_function("hash map", |b| b.iter(|| filled_hash_map(black_box(&keys))));
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let keys = keys();
+    let table = filled_table(&keys);
+    let hash_map = filled_hash_map(&keys);
+    let mut group = c.benchmark_group("lookup");
+    group.bench_function("table", |b| {
+        b.iter(|| keys.iter().filter_map(|key| table.get(black_box(*key))).sum::<f64>())
+    });
+    group.bench_function("hash map", |b| {
+        b.iter(|| keys.iter().filter_map(|key| hash_map.get(black_box(key))).sum::<f64>())
+    });
+    group.finish();
+}
+
+fn delete(c: &mut Criterion) {
+    let keys = keys();
+    let mut group = c.benchmark_group("delete");
+    group.bench_function("table", |b| {
+        b.iter_batched_ref(
+            || filled_table(&keys),
+            |table| keys.iter().for_each(|key| {
+                table.delete(black_box(*key));
+            }),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("hash map", |b| {
+        b.iter_batched_ref(
+            || filled_hash_map(&keys),
+            |hash_map| keys.iter().for_each(|key| {
+                hash_map.remove(black_box(key));
+            }),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, insert, lookup, delete);
+criterion_main!(benches);
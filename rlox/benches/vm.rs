@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const ITERATIONS: usize = 10_000;
+
+struct NullReporter;
+
+impl rlox::Reporter for NullReporter {
+    fn add_diagnostic(&self, _location: &rlox::Region, _message: &str) {}
+
+    fn add_message(&self, _message: &str) {}
+
+    fn has_diagnostics(&self) -> bool {
+        false
+    }
+}
+
+fn arithmetic_chunk() -> rlox::Chunk {
+    let mut chunk = rlox::Chunk::new();
+    let one = rlox::Value::Number(1.0);
+    let two = rlox::Value::Number(2.0);
+
+    chunk.write_constant(one, rlox::Region::default());
+    for _ in 0..ITERATIONS {
+        chunk.write_constant(two, rlox::Region::default());
+        chunk.write_op_code(rlox::OpCode::Multiply, rlox::Region::default());
+        chunk.write_constant(one, rlox::Region::default());
+        chunk.write_op_code(rlox::OpCode::Subtract, rlox::Region::default());
+        chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::default());
+    }
+    chunk.write_op_code(rlox::OpCode::Return, rlox::Region::default());
+    chunk
+}
+
+// The dispatch loop the vm used before the instruction pointer became an index, kept here as a
+// baseline for the cost of the bounds checks.
+fn run_raw_pointer(chunk: &rlox::Chunk, stack: &mut Vec<rlox::Value>) {
+    let mut ip = chunk.code.as_ptr();
+    let mut read_byte = || unsafe {
+        // DANGER!
+        let byte = *ip;
+        ip = ip.add(1);
+        byte
+    };
+    loop {
+        match rlox::OpCode::try_from(read_byte()) {
+            Ok(rlox::OpCode::Constant) => {
+                let index = read_byte() as usize;
+                if let Some(constant) = chunk.constants.get(index) {
+                    stack.push(*constant);
+                }
+            }
+            Ok(rlox::OpCode::Negate) => {
+                if let Some(rlox::Value::Number(a)) = stack.pop() {
+                    stack.push(rlox::Value::Number(-a));
+                }
+            }
+            Ok(op_code @ (rlox::OpCode::Add
+            | rlox::OpCode::Subtract
+            | rlox::OpCode::Multiply
+            | rlox::OpCode::Divide)) => {
+                if let (Some(rlox::Value::Number(b)), Some(rlox::Value::Number(a))) =
+                    (stack.pop(), stack.pop())
+                {
+                    let r = match op_code {
+                        rlox::OpCode::Add => a + b,
+                        rlox::OpCode::Subtract => a - b,
+                        rlox::OpCode::Multiply => a * b,
+                        _ => a / b,
+                    };
+                    stack.push(rlox::Value::Number(r));
+                }
+            }
+            _ => return,
+        }
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    let chunk = arithmetic_chunk();
+    let reporter = NullReporter;
+
+    let mut group = c.benchmark_group("dispatch");
+    // Verifying and copying the chunk happen once, so only the dispatch loop is timed
+    group.bench_function("vm", |b| {
+        let mut vm = rlox::Vm::new(&reporter);
+        vm.load_chunk(&chunk).expect("invalid chunk");
+        b.iter(|| vm.run_loaded())
+    });
+    group.bench_function("raw pointer", |b| {
+        let mut stack = Vec::with_capacity(256);
+        b.iter(|| {
+            stack.clear();
+            run_raw_pointer(black_box(&chunk), &mut stack)
+        })
+    });
+    group.bench_function("verify", |b| b.iter(|| black_box(&chunk).verify()));
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -1,6 +1,10 @@
-use crate::{location, value};
+use crate::{location, object, table, value};
+use std::fmt;
+
+const CONSTANT_LONG_MAX: usize = (1 << 24) - 1;
 
 #[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum OpCode {
     Constant = 1,
     Add = 2,
@@ -9,13 +13,323 @@ pub enum OpCode {
     Divide = 5,
     Negate = 6,
     Return = 7,
+    Nil = 8,
+    True = 9,
+    False = 10,
+    Not = 11,
+    Equal = 12,
+    Greater = 13,
+    Less = 14,
+    Print = 15,
+    Pop = 16,
+    DefineGlobal = 17,
+    GetGlobal = 18,
+    SetGlobal = 19,
+    GetLocal = 20,
+    SetLocal = 21,
+    Jump = 22,
+    JumpIfFalse = 23,
+    Loop = 24,
+    Call = 25,
+    Closure = 26,
+    GetUpvalue = 27,
+    SetUpvalue = 28,
+    CloseUpvalue = 29,
+    Class = 30,
+    GetProperty = 31,
+    SetProperty = 32,
+    Method = 33,
+    Invoke = 34,
+    Inherit = 35,
+    GetSuper = 36,
+    SuperInvoke = 37,
+    ConstantLong = 38,
+    DefineGlobalLong = 39,
+    GetGlobalLong = 40,
+    SetGlobalLong = 41,
+    ClosureLong = 42,
+    ClassLong = 43,
+    GetPropertyLong = 44,
+    SetPropertyLong = 45,
+    MethodLong = 46,
+    InvokeLong = 47,
+    GetSuperLong = 48,
+    SuperInvokeLong = 49,
 }
 
-#[derive(Default)]
+// Op codes whose first operand is a constant index, paired with the form taking a 24-bit index.
+const LONG_FORMS: [(OpCode, OpCode); 12] = [
+    (OpCode::Constant, OpCode::ConstantLong),
+    (OpCode::DefineGlobal, OpCode::DefineGlobalLong),
+    (OpCode::GetGlobal, OpCode::GetGlobalLong),
+    (OpCode::SetGlobal, OpCode::SetGlobalLong),
+    (OpCode::Closure, OpCode::ClosureLong),
+    (OpCode::Class, OpCode::ClassLong),
+    (OpCode::GetProperty, OpCode::GetPropertyLong),
+    (OpCode::SetProperty, OpCode::SetPropertyLong),
+    (OpCode::Method, OpCode::MethodLong),
+    (OpCode::Invoke, OpCode::InvokeLong),
+    (OpCode::GetSuper, OpCode::GetSuperLong),
+    (OpCode::SuperInvoke, OpCode::SuperInvokeLong),
+];
+
+impl OpCode {
+    pub fn operand_count(&self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::Closure
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Class
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::Method
+            | OpCode::GetSuper => 1,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::Invoke | OpCode::SuperInvoke => 2,
+            OpCode::ConstantLong
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong
+            | OpCode::ClosureLong
+            | OpCode::ClassLong
+            | OpCode::GetPropertyLong
+            | OpCode::SetPropertyLong
+            | OpCode::MethodLong
+            | OpCode::GetSuperLong => 3,
+            OpCode::InvokeLong | OpCode::SuperInvokeLong => 4,
+            OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Negate
+            | OpCode::Return
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Not
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Print
+            | OpCode::Pop
+            | OpCode::CloseUpvalue
+            | OpCode::Inherit => 0,
+        }
+    }
+
+    /// The form of an op code taking a constant index that takes a 24-bit index instead.
+    pub fn long_form(&self) -> Option<OpCode> {
+        LONG_FORMS.iter().find(|(short, _)| short == self).map(|(_, long)| *long)
+    }
+
+    /// The op code a long form widens.
+    pub fn short_form(&self) -> Option<OpCode> {
+        LONG_FORMS.iter().find(|(_, long)| long == self).map(|(short, _)| *short)
+    }
+
+    /// Whether the op code takes a 24-bit constant index.
+    pub fn is_long(&self) -> bool {
+        self.short_form().is_some()
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            x if x == OpCode::Constant as u8 => Ok(OpCode::Constant),
+            x if x == OpCode::Add as u8 => Ok(OpCode::Add),
+            x if x == OpCode::Subtract as u8 => Ok(OpCode::Subtract),
+            x if x == OpCode::Multiply as u8 => Ok(OpCode::Multiply),
+            x if x == OpCode::Divide as u8 => Ok(OpCode::Divide),
+            x if x == OpCode::Negate as u8 => Ok(OpCode::Negate),
+            x if x == OpCode::Return as u8 => Ok(OpCode::Return),
+            x if x == OpCode::Nil as u8 => Ok(OpCode::Nil),
+            x if x == OpCode::True as u8 => Ok(OpCode::True),
+            x if x == OpCode::False as u8 => Ok(OpCode::False),
+            x if x == OpCode::Not as u8 => Ok(OpCode::Not),
+            x if x == OpCode::Equal as u8 => Ok(OpCode::Equal),
+            x if x == OpCode::Greater as u8 => Ok(OpCode::Greater),
+            x if x == OpCode::Less as u8 => Ok(OpCode::Less),
+            x if x == OpCode::Print as u8 => Ok(OpCode::Print),
+            x if x == OpCode::Pop as u8 => Ok(OpCode::Pop),
+            x if x == OpCode::DefineGlobal as u8 => Ok(OpCode::DefineGlobal),
+            x if x == OpCode::GetGlobal as u8 => Ok(OpCode::GetGlobal),
+            x if x == OpCode::SetGlobal as u8 => Ok(OpCode::SetGlobal),
+            x if x == OpCode::GetLocal as u8 => Ok(OpCode::GetLocal),
+            x if x == OpCode::SetLocal as u8 => Ok(OpCode::SetLocal),
+            x if x == OpCode::Jump as u8 => Ok(OpCode::Jump),
+            x if x == OpCode::JumpIfFalse as u8 => Ok(OpCode::JumpIfFalse),
+            x if x == OpCode::Loop as u8 => Ok(OpCode::Loop),
+            x if x == OpCode::Call as u8 => Ok(OpCode::Call),
+            x if x == OpCode::Closure as u8 => Ok(OpCode::Closure),
+            x if x == OpCode::GetUpvalue as u8 => Ok(OpCode::GetUpvalue),
+            x if x == OpCode::SetUpvalue as u8 => Ok(OpCode::SetUpvalue),
+            x if x == OpCode::CloseUpvalue as u8 => Ok(OpCode::CloseUpvalue),
+            x if x == OpCode::Class as u8 => Ok(OpCode::Class),
+            x if x == OpCode::GetProperty as u8 => Ok(OpCode::GetProperty),
+            x if x == OpCode::SetProperty as u8 => Ok(OpCode::SetProperty),
+            x if x == OpCode::Method as u8 => Ok(OpCode::Method),
+            x if x == OpCode::Invoke as u8 => Ok(OpCode::Invoke),
+            x if x == OpCode::Inherit as u8 => Ok(OpCode::Inherit),
+            x if x == OpCode::GetSuper as u8 => Ok(OpCode::GetSuper),
+            x if x == OpCode::SuperInvoke as u8 => Ok(OpCode::SuperInvoke),
+            x if x == OpCode::ConstantLong as u8 => Ok(OpCode::ConstantLong),
+            x if x == OpCode::DefineGlobalLong as u8 => Ok(OpCode::DefineGlobalLong),
+            x if x == OpCode::GetGlobalLong as u8 => Ok(OpCode::GetGlobalLong),
+            x if x == OpCode::SetGlobalLong as u8 => Ok(OpCode::SetGlobalLong),
+            x if x == OpCode::ClosureLong as u8 => Ok(OpCode::ClosureLong),
+            x if x == OpCode::ClassLong as u8 => Ok(OpCode::ClassLong),
+            x if x == OpCode::GetPropertyLong as u8 => Ok(OpCode::GetPropertyLong),
+            x if x == OpCode::SetPropertyLong as u8 => Ok(OpCode::SetPropertyLong),
+            x if x == OpCode::MethodLong as u8 => Ok(OpCode::MethodLong),
+            x if x == OpCode::InvokeLong as u8 => Ok(OpCode::InvokeLong),
+            x if x == OpCode::GetSuperLong as u8 => Ok(OpCode::GetSuperLong),
+            x if x == OpCode::SuperInvokeLong as u8 => Ok(OpCode::SuperInvokeLong),
+            _ => Err(byte),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum VerifyError {
+    UnknownOpCode { offset: usize, byte: u8 },
+    MissingOperand { offset: usize, op_code: OpCode },
+    InvalidConstant { offset: usize, index: usize },
+    InvalidJump { offset: usize, target: isize },
+    MissingLocation { offset: usize },
+}
+
+impl VerifyError {
+    pub fn offset(&self) -> usize {
+        match self {
+            VerifyError::UnknownOpCode { offset, .. }
+            | VerifyError::MissingOperand { offset, .. }
+            | VerifyError::InvalidConstant { offset, .. }
+            | VerifyError::InvalidJump { offset, .. }
+            | VerifyError::MissingLocation { offset } => *offset,
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::UnknownOpCode { offset, byte } => {
+                write!(f, "Unknown op_code {byte} at offset {offset}")
+            }
+            VerifyError::MissingOperand { offset, op_code } => {
+                write!(f, "Missing operand for {op_code:?} at offset {offset}")
+            }
+            VerifyError::InvalidConstant { offset, index } => {
+                write!(f, "Invalid constant index {index} at offset {offset}")
+            }
+            VerifyError::InvalidJump { offset, target } => {
+                write!(f, "Invalid jump target {target} at offset {offset}")
+            }
+            VerifyError::MissingLocation { offset } => {
+                write!(f, "Missing location for offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+// Constants are shared when they are identical: numbers compare by their bits, so that 0 and -0
+// keep separate slots, and objects by reference.
+#[derive(Clone, Copy)]
+struct ConstantKey(value::Value);
+
+impl PartialEq for ConstantKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0, other.0) {
+            (value::Value::Number(a), value::Value::Number(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl table::TableKey for ConstantKey {
+    fn hash_code(&self) -> u32 {
+        match self.0 {
+            value::Value::Bool(value) => value as u32,
+            value::Value::Nil => 2,
+            value::Value::Number(value) => object::hash_bytes(&value.to_bits().to_le_bytes()),
+            // Only strings hash by content, and they are interned, so hashing every other object
+            // by address keeps them from all landing in one probe cluster.
+            value::Value::Obj(obj) => match obj.as_string() {
+                Some(string) => string.hash,
+                None => object::hash_bytes(&obj.address().to_le_bytes()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct LocationRun {
+    start: usize,
+    region: location::Region,
+}
+
+// Consecutive bytes written for the same region share a run, so operands add nothing and a
+// simple statement usually needs a single entry.
+#[derive(Clone, Default)]
+pub struct LocationTable {
+    runs: Vec<LocationRun>,
+    len: usize,
+}
+
+impl LocationTable {
+    pub fn new() -> Self {
+        Self { runs: vec![], len: 0 }
+    }
+
+    pub fn push(&mut self, region: location::Region) {
+        if self.runs.last().is_none_or(|run| run.region != region) {
+            self.runs.push(LocationRun {
+                start: self.len,
+                region,
+            });
+        }
+        self.len += 1;
+    }
+
+    /// The number of bytes that have a region.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn get(&self, offset: usize) -> Option<location::Region> {
+        if offset >= self.len {
+            return None;
+        }
+        let index = self.runs.partition_point(|run| run.start <= offset);
+        Some(self.runs[index - 1].region)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: value::ValueArray,
-    pub locations: Vec<location::Region>,
+    pub locations: LocationTable,
+    constant_slots: table::Table<ConstantKey, usize>,
 }
 
 impl Chunk {
@@ -23,7 +337,8 @@ impl Chunk {
         Self {
             code: vec![],
             constants: value::ValueArray::new(),
-            locations: vec![],
+            locations: LocationTable::new(),
+            constant_slots: table::Table::new(),
         }
     }
 
@@ -41,11 +356,208 @@ impl Chunk {
         Self::new()
     }
 
+    /// Drops the lookup used to share constant slots, which is only needed while the chunk is
+    /// being written. Constants added afterwards are no longer shared.
+    pub fn finish_constants(&mut self) {
+        self.constant_slots = table::Table::new();
+    }
+
     pub fn add_constant(&mut self, value: value::Value) -> usize {
-        self.constants.write(value)
+        if let Some(index) = self.constant_slots.get(ConstantKey(value)) {
+            return index;
+        }
+        let index = self.constants.write(value);
+        self.constant_slots.set(ConstantKey(value), index);
+        index
+    }
+
+    /// Adds `value` like `add_constant`, unless it is new and its index would not fit in a
+    /// 24-bit operand, in which case the chunk is left unchanged.
+    pub fn try_add_constant(&mut self, value: value::Value) -> Option<usize> {
+        if self.constants.len() > CONSTANT_LONG_MAX && self.constant_slots.get(ConstantKey(value)).is_none() {
+            return None;
+        }
+        Some(self.add_constant(value))
+    }
+
+    /// Writes the instruction that loads `value`, using `ConstantLong` with a 24-bit operand once
+    /// the constant no longer fits in a byte. Returns `None` if the constant table is full.
+    pub fn write_constant(&mut self, value: value::Value, location: location::Region) -> Option<usize> {
+        let index = self.try_add_constant(value)?;
+        self.write_constant_op(OpCode::Constant, index, location);
+        Some(index)
+    }
+
+    /// Writes `op_code` with the constant `index` as its first operand, switching to the long form
+    /// when the index does not fit in a byte. Returns `false`, writing nothing, if `op_code` takes
+    /// no constant or the index does not fit in 24 bits either.
+    pub fn write_constant_op(&mut self, op_code: OpCode, index: usize, location: location::Region) -> bool {
+        let Some(long_form) = op_code.long_form() else {
+            return false;
+        };
+        if let Ok(byte) = u8::try_from(index) {
+            self.write_op_code(op_code, location);
+            self.write_byte(byte, location);
+        } else if index <= CONSTANT_LONG_MAX {
+            let [_, high, middle, low] = (index as u32).to_be_bytes();
+            self.write_op_code(long_form, location);
+            self.write_byte(high, location);
+            self.write_byte(middle, location);
+            self.write_byte(low, location);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn region_at(&self, offset: usize) -> Option<location::Region> {
+        self.locations.get(offset)
     }
-    
+
     pub fn has_code(&self) -> bool {
         !self.code.is_empty()
     }
+
+    pub fn read_short(&self, offset: usize) -> Option<u16> {
+        let high = *self.code.get(offset)?;
+        let low = *self.code.get(offset + 1)?;
+        Some(u16::from_be_bytes([high, low]))
+    }
+
+    pub fn read_long(&self, offset: usize) -> Option<usize> {
+        let bytes = self.code.get(offset..offset + 3)?;
+        Some(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    }
+
+    /// Reads the constant index operand at `offset`, which is 24 bits for long op codes.
+    pub fn read_index(&self, offset: usize, long: bool) -> Option<usize> {
+        if long {
+            self.read_long(offset)
+        } else {
+            self.code.get(offset).map(|byte| *byte as usize)
+        }
+    }
+
+    /// The number of upvalues captured by the function constant at `index`, if it is a function.
+    pub fn upvalue_count(&self, index: usize) -> Option<usize> {
+        match self.constants.get(index)? {
+            value::Value::Obj(obj) => obj.as_function().map(|function| function.upvalue_count),
+            _ => None,
+        }
+    }
+
+    /// Check every op code, operand, constant index and jump target before the chunk is executed.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        if self.locations.len() < self.code.len() {
+            return Err(VerifyError::MissingLocation {
+                offset: self.locations.len(),
+            });
+        }
+
+        let mut instructions = vec![false; self.code.len()];
+        let mut jumps = vec![];
+        let mut offset = 0;
+        while offset < self.code.len() {
+            instructions[offset] = true;
+            let byte = self.code[offset];
+            let op_code =
+                OpCode::try_from(byte).map_err(|byte| VerifyError::UnknownOpCode { offset, byte })?;
+            let mut operands = op_code.operand_count();
+            if offset + operands >= self.code.len() {
+                return Err(VerifyError::MissingOperand { offset, op_code });
+            }
+            // Long forms only differ in the width of the index, so they are checked alike.
+            let index = self.read_index(offset + 1, op_code.is_long()).unwrap_or_default();
+            match op_code.short_form().unwrap_or(op_code) {
+                OpCode::Constant
+                | OpCode::DefineGlobal
+                | OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::Class
+                | OpCode::GetProperty
+                | OpCode::SetProperty
+                | OpCode::Method
+                | OpCode::Invoke
+                | OpCode::GetSuper
+                | OpCode::SuperInvoke
+                    if self.constants.get(index).is_none() =>
+                {
+                    return Err(VerifyError::InvalidConstant { offset, index });
+                }
+                OpCode::Closure => {
+                    let Some(upvalue_count) = self.upvalue_count(index) else {
+                        return Err(VerifyError::InvalidConstant { offset, index });
+                    };
+                    // Each captured variable adds an is_local byte and an index byte.
+                    operands += 2 * upvalue_count;
+                    if offset + operands >= self.code.len() {
+                        return Err(VerifyError::MissingOperand { offset, op_code });
+                    }
+                }
+                OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                    let jump = self.read_short(offset + 1).unwrap_or_default() as isize;
+                    let next = (offset + 3) as isize;
+                    let target = if op_code == OpCode::Loop { next - jump } else { next + jump };
+                    jumps.push((offset, target));
+                }
+                _ => (),
+            }
+            offset += 1 + operands;
+        }
+
+        for (offset, target) in jumps {
+            let is_instruction = usize::try_from(target)
+                .is_ok_and(|target| instructions.get(target).copied().unwrap_or(false));
+            if !is_instruction {
+                return Err(VerifyError::InvalidJump { offset, target });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn location_runs() {
+        let first = location::Region::new_single_line(1, 0, 3);
+        let second = location::Region::new_single_line(1, 4, 5);
+        let third = location::Region::new_single_line(2, 0, 1);
+
+        let mut chunk = Chunk::new();
+        chunk.write_op_code(OpCode::Constant, first);
+        chunk.write_byte(0, first);
+        chunk.write_op_code(OpCode::Constant, first);
+        chunk.write_byte(1, first);
+        chunk.write_op_code(OpCode::Add, second);
+        chunk.write_op_code(OpCode::Print, first);
+        chunk.write_op_code(OpCode::Return, third);
+
+        assert_eq!(chunk.locations.len(), chunk.code.len());
+        assert_eq!(chunk.locations.run_count(), 4);
+        let regions: Vec<_> = (0..chunk.code.len()).map(|offset| chunk.region_at(offset)).collect();
+        assert_eq!(regions, [first, first, first, first, second, first, third].map(Some));
+        assert_eq!(chunk.region_at(chunk.code.len()), None);
+        assert_eq!(Chunk::new().region_at(0), None);
+    }
+
+    #[test]
+    fn shared_object_constants() {
+        use table::TableKey;
+        let mut heap = crate::heap::Heap::new();
+        let functions: Vec<_> = (0..3)
+            .map(|_| heap.allocate(object::Obj::Function(object::ObjFunction::new(None))))
+            .map(value::Value::Obj)
+            .collect();
+        let hashes: Vec<_> = functions.iter().map(|function| ConstantKey(*function).hash_code()).collect();
+        assert!(hashes[0] != hashes[1] && hashes[1] != hashes[2] && hashes[0] != hashes[2]);
+
+        let mut chunk = Chunk::new();
+        assert_eq!(functions.iter().map(|function| chunk.add_constant(*function)).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(chunk.add_constant(functions[1]), 1);
+        chunk.finish_constants();
+        assert_eq!(chunk.add_constant(functions[1]), 3);
+    }
 }
@@ -11,45 +11,153 @@ pub fn disassemble_chunk(output: &dyn Reporter, chunk: &chunk::Chunk, name: &str
 
 pub fn disassemble_instruction(output: &dyn Reporter, chunk: &chunk::Chunk, index: usize) -> usize {
     let header = format!("{index:04}");
-    let line = if index > 0 && chunk.locations[index].has_same_line(&chunk.locations[index - 1]) {
+    let region = chunk.region_at(index).unwrap_or_default();
+    let previous = index.checked_sub(1).and_then(|previous| chunk.region_at(previous));
+    let line = if previous.is_some_and(|previous| region.has_same_line(&previous)) {
         "   |".to_string()
     } else {
-        format!("{:4}", chunk.locations[index].start.line)
+        format!("{:4}", region.start.line)
     };
-    let op_code = byte_to_op_code(chunk.code[index]);
+    let op_code = chunk::OpCode::try_from(chunk.code[index]).ok();
     let (increment, content) = match op_code {
         Some(chunk::OpCode::Return) => simple_instruction("OP_RETURN"),
-        Some(chunk::OpCode::Constant) => constant_instruction("OP_CONSTANT", chunk, index),
+        Some(chunk::OpCode::Constant) => constant_instruction("OP_CONSTANT", false, chunk, index),
         Some(chunk::OpCode::Negate) => simple_instruction("OP_NEGATE"),
         Some(chunk::OpCode::Add) => simple_instruction("OP_ADD"),
         Some(chunk::OpCode::Subtract) => simple_instruction("OP_SUBTRACT"),
         Some(chunk::OpCode::Multiply) => simple_instruction("OP_MULTIPLY"),
         Some(chunk::OpCode::Divide) => simple_instruction("OP_DIVIDE"),
+        Some(chunk::OpCode::Nil) => simple_instruction("OP_NIL"),
+        Some(chunk::OpCode::True) => simple_instruction("OP_TRUE"),
+        Some(chunk::OpCode::False) => simple_instruction("OP_FALSE"),
+        Some(chunk::OpCode::Not) => simple_instruction("OP_NOT"),
+        Some(chunk::OpCode::Equal) => simple_instruction("OP_EQUAL"),
+        Some(chunk::OpCode::Greater) => simple_instruction("OP_GREATER"),
+        Some(chunk::OpCode::Less) => simple_instruction("OP_LESS"),
+        Some(chunk::OpCode::Print) => simple_instruction("OP_PRINT"),
+        Some(chunk::OpCode::Pop) => simple_instruction("OP_POP"),
+        Some(chunk::OpCode::DefineGlobal) => constant_instruction("OP_DEFINE_GLOBAL", false, chunk, index),
+        Some(chunk::OpCode::GetGlobal) => constant_instruction("OP_GET_GLOBAL", false, chunk, index),
+        Some(chunk::OpCode::SetGlobal) => constant_instruction("OP_SET_GLOBAL", false, chunk, index),
+        Some(chunk::OpCode::GetLocal) => byte_instruction("OP_GET_LOCAL", chunk, index),
+        Some(chunk::OpCode::SetLocal) => byte_instruction("OP_SET_LOCAL", chunk, index),
+        Some(chunk::OpCode::Jump) => jump_instruction("OP_JUMP", 1, chunk, index),
+        Some(chunk::OpCode::JumpIfFalse) => jump_instruction("OP_JUMP_IF_FALSE", 1, chunk, index),
+        Some(chunk::OpCode::Loop) => jump_instruction("OP_LOOP", -1, chunk, index),
+        Some(chunk::OpCode::Call) => byte_instruction("OP_CALL", chunk, index),
+        Some(chunk::OpCode::Closure) => closure_instruction("OP_CLOSURE", false, chunk, index),
+        Some(chunk::OpCode::GetUpvalue) => byte_instruction("OP_GET_UPVALUE", chunk, index),
+        Some(chunk::OpCode::SetUpvalue) => byte_instruction("OP_SET_UPVALUE", chunk, index),
+        Some(chunk::OpCode::CloseUpvalue) => simple_instruction("OP_CLOSE_UPVALUE"),
+        Some(chunk::OpCode::Class) => constant_instruction("OP_CLASS", false, chunk, index),
+        Some(chunk::OpCode::GetProperty) => constant_instruction("OP_GET_PROPERTY", false, chunk, index),
+        Some(chunk::OpCode::SetProperty) => constant_instruction("OP_SET_PROPERTY", false, chunk, index),
+        Some(chunk::OpCode::Method) => constant_instruction("OP_METHOD", false, chunk, index),
+        Some(chunk::OpCode::Invoke) => invoke_instruction("OP_INVOKE", false, chunk, index),
+        Some(chunk::OpCode::Inherit) => simple_instruction("OP_INHERIT"),
+        Some(chunk::OpCode::GetSuper) => constant_instruction("OP_GET_SUPER", false, chunk, index),
+        Some(chunk::OpCode::SuperInvoke) => invoke_instruction("OP_SUPER_INVOKE", false, chunk, index),
+        Some(chunk::OpCode::ConstantLong) => constant_instruction("OP_CONSTANT_LONG", true, chunk, index),
+        Some(chunk::OpCode::DefineGlobalLong) => {
+            constant_instruction("OP_DEFINE_GLOBAL_LONG", true, chunk, index)
+        }
+        Some(chunk::OpCode::GetGlobalLong) => constant_instruction("OP_GET_GLOBAL_LONG", true, chunk, index),
+        Some(chunk::OpCode::SetGlobalLong) => constant_instruction("OP_SET_GLOBAL_LONG", true, chunk, index),
+        Some(chunk::OpCode::ClosureLong) => closure_instruction("OP_CLOSURE_LONG", true, chunk, index),
+        Some(chunk::OpCode::ClassLong) => constant_instruction("OP_CLASS_LONG", true, chunk, index),
+        Some(chunk::OpCode::GetPropertyLong) => {
+            constant_instruction("OP_GET_PROPERTY_LONG", true, chunk, index)
+        }
+        Some(chunk::OpCode::SetPropertyLong) => {
+            constant_instruction("OP_SET_PROPERTY_LONG", true, chunk, index)
+        }
+        Some(chunk::OpCode::MethodLong) => constant_instruction("OP_METHOD_LONG", true, chunk, index),
+        Some(chunk::OpCode::InvokeLong) => invoke_instruction("OP_INVOKE_LONG", true, chunk, index),
+        Some(chunk::OpCode::GetSuperLong) => constant_instruction("OP_GET_SUPER_LONG", true, chunk, index),
+        Some(chunk::OpCode::SuperInvokeLong) => {
+            invoke_instruction("OP_SUPER_INVOKE_LONG", true, chunk, index)
+        }
         None => (1, format!("Unknown op_code {}", chunk.code[index])),
     };
     output.add_message(&format!("{header} {line} {content}"));
+    if let Some(op_code @ (chunk::OpCode::Closure | chunk::OpCode::ClosureLong)) = op_code {
+        (index + 1 + index_width(op_code.is_long())..index + increment)
+            .step_by(2)
+            .for_each(|offset| output.add_message(&upvalue_capture(chunk, offset)));
+    }
     index + increment
 }
 
-fn byte_to_op_code(byte: u8) -> Option<chunk::OpCode> {
-    // match byte {
-    //     x if x == chunk::OpCode::Constant as u8 => Some(chunk::OpCode::Constant),
-    //     x if x == chunk::OpCode::Return as u8 => Some(chunk::OpCode::Return),
-    //     _ => None,
-    // }
-    let op_code: chunk::OpCode = unsafe { std::mem::transmute(byte) };
-    Some(op_code)
-}
-
 fn simple_instruction(name: &str) -> (usize, String) {
     (1, name.to_string())
 }
 
-fn constant_instruction(name: &str, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
-    let constant_index = chunk.code[index + 1];
+fn byte_instruction(name: &str, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
+    let Some(slot) = chunk.code.get(index + 1) else {
+        return (1, format!("{name:<16} <missing operand>"));
+    };
+    (2, format!("{name:<16} {slot:4}"))
+}
+
+fn jump_instruction(name: &str, sign: isize, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
+    let Some(jump) = chunk.read_short(index + 1) else {
+        return (1, format!("{name:<16} <missing operand>"));
+    };
+    let target = index as isize + 3 + sign * jump as isize;
+    (3, format!("{name:<16} {index:4} -> {target}"))
+}
+
+fn index_width(long: bool) -> usize {
+    if long {
+        3
+    } else {
+        1
+    }
+}
+
+fn constant_instruction(name: &str, long: bool, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
+    let Some(constant_index) = chunk.read_index(index + 1, long) else {
+        return (1, format!("{name:<16} <missing operand>"));
+    };
+    let constant = chunk
+        .constants
+        .get(constant_index)
+        .map_or_else(|| "".to_string(), |o| o.to_string());
+    (1 + index_width(long), format!("{name:<16} {constant_index:4} {constant}"))
+}
+
+fn invoke_instruction(name: &str, long: bool, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
+    let width = index_width(long);
+    let (Some(constant_index), Some(arg_count)) =
+        (chunk.read_index(index + 1, long), chunk.code.get(index + 1 + width))
+    else {
+        return (1, format!("{name:<16} <missing operand>"));
+    };
     let constant = chunk
         .constants
-        .get(constant_index as usize)
+        .get(constant_index)
         .map_or_else(|| "".to_string(), |o| o.to_string());
-    (2, format!("{name:<16} {constant_index:4} {constant}"))
+    (2 + width, format!("{name:<16} ({arg_count} args) {constant_index:4} {constant}"))
+}
+
+fn closure_instruction(name: &str, long: bool, chunk: &chunk::Chunk, index: usize) -> (usize, String) {
+    let (increment, content) = constant_instruction(name, long, chunk, index);
+    let upvalue_count = chunk
+        .read_index(index + 1, long)
+        .and_then(|constant_index| chunk.upvalue_count(constant_index))
+        .unwrap_or_default();
+    let increment = (increment + 2 * upvalue_count).min(chunk.code.len() - index);
+    (increment, content)
+}
+
+fn upvalue_capture(chunk: &chunk::Chunk, offset: usize) -> String {
+    let kind = match chunk.code[offset] {
+        0 => "upvalue",
+        _ => "local",
+    };
+    let index = chunk
+        .code
+        .get(offset + 1)
+        .map_or_else(|| "<missing operand>".to_string(), |index| index.to_string());
+    format!("{offset:04}    |                     {kind} {index}")
 }
@@ -0,0 +1,324 @@
+use crate::object::{hash_string, Obj, ObjRef, ObjString, Upvalue};
+use crate::table::Table;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::mem::size_of;
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const GC_MIN_THRESHOLD: usize = 1024 * 1024;
+
+pub struct Heap {
+    objects: Vec<ObjRef>,
+    strings: Table<ObjRef, ()>,
+    gray_stack: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    #[cfg(feature = "debug_log_gc")]
+    log: Vec<String>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: vec![],
+            strings: Table::new(),
+            gray_stack: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_MIN_THRESHOLD,
+            #[cfg(feature = "debug_log_gc")]
+            log: vec![],
+        }
+    }
+
+    pub fn intern(&mut self, chars: &str) -> ObjRef {
+        let hash = hash_string(chars);
+        if let Some(string) = self.strings.find_string(chars, hash) {
+            return string;
+        }
+        let string = self.allocate(Obj::String(ObjString::new(chars, hash)));
+        self.strings.set(string, ());
+        string
+    }
+
+    // Allocating never collects. Callers collect at points where everything they still need is
+    // reachable from the roots they pass in, and then allocate.
+    pub fn allocate(&mut self, obj: Obj) -> ObjRef {
+        let size = size_of_obj(&obj);
+        let obj_ref = ObjRef::allocate(obj);
+        self.bytes_allocated += size;
+        self.objects.push(obj_ref);
+
+        #[cfg(feature = "debug_log_gc")]
+        self.log.push(format!("{:p} allocate {size} for {obj_ref}", &*obj_ref));
+
+        obj_ref
+    }
+
+    /// Updates a method or field table owned by an object, counting any growth of its entries as
+    /// allocated. Sweeping the object frees the table at its final size.
+    pub fn update_table(
+        &mut self,
+        table: &RefCell<Table<ObjRef, Value>>,
+        update: impl FnOnce(&mut Table<ObjRef, Value>),
+    ) {
+        let mut table = table.borrow_mut();
+        let before = table.allocated_bytes();
+        update(&mut table);
+        self.bytes_allocated += table.allocated_bytes() - before;
+    }
+
+    /// Takes the lines logged since the last call. The heap has no reporter, so whoever owns it
+    /// passes them on.
+    #[cfg(feature = "debug_log_gc")]
+    pub fn take_log(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.log)
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "debug_stress_gc") || self.bytes_allocated > self.next_gc
+    }
+
+    pub fn collect_garbage(&mut self, roots: impl Iterator<Item = Value>) {
+        #[cfg(feature = "debug_log_gc")]
+        let before = self.bytes_allocated;
+        #[cfg(feature = "debug_log_gc")]
+        self.log.push("-- gc begin".to_string());
+
+        roots.for_each(|root| self.mark_value(root));
+        self.trace_references();
+        self.strings.retain(|string, _| string.is_marked());
+        self.sweep();
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(GC_MIN_THRESHOLD);
+
+        #[cfg(feature = "debug_log_gc")]
+        {
+            self.log.push("-- gc end".to_string());
+            self.log.push(format!(
+                "   collected {} bytes (from {before} to {}) next at {}",
+                before - self.bytes_allocated,
+                self.bytes_allocated,
+                self.next_gc
+            ));
+        }
+    }
+
+    fn mark_value(&mut self, value: Value) {
+        if let Value::Obj(obj) = value {
+            self.mark_object(obj);
+        }
+    }
+
+    fn mark_object(&mut self, obj: ObjRef) {
+        if !obj.mark() {
+            return;
+        }
+
+        #[cfg(feature = "debug_log_gc")]
+        self.log.push(format!("{:p} mark {obj}", &*obj));
+
+        self.gray_stack.push(obj);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray_stack.pop() {
+            self.blacken_object(obj);
+        }
+    }
+
+    fn blacken_object(&mut self, obj: ObjRef) {
+        #[cfg(feature = "debug_log_gc")]
+        self.log.push(format!("{:p} blacken {obj}", &*obj));
+
+        match &*obj {
+            Obj::String(_) | Obj::Native(_) => (),
+            Obj::Function(function) => {
+                if let Some(name) = function.name {
+                    self.mark_object(name);
+                }
+                function
+                    .chunk
+                    .constants
+                    .iter()
+                    .for_each(|constant| self.mark_value(*constant));
+            }
+            Obj::Closure(closure) => {
+                self.mark_object(closure.function);
+                closure
+                    .upvalues
+                    .iter()
+                    .for_each(|upvalue| self.mark_object(*upvalue));
+            }
+            Obj::Upvalue(upvalue) => {
+                if let Upvalue::Closed(value) = upvalue.location.get() {
+                    self.mark_value(value);
+                }
+            }
+            Obj::Class(class) => {
+                self.mark_object(class.name);
+                self.mark_table(&class.methods.borrow());
+            }
+            Obj::Instance(instance) => {
+                self.mark_object(instance.class);
+                self.mark_table(&instance.fields.borrow());
+            }
+            Obj::BoundMethod(bound_method) => {
+                self.mark_value(bound_method.receiver);
+                self.mark_object(bound_method.method);
+            }
+        }
+    }
+
+    fn mark_table(&mut self, table: &Table<ObjRef, Value>) {
+        table.iter().for_each(|(key, value)| {
+            self.mark_object(key);
+            self.mark_value(value);
+        });
+    }
+
+    fn sweep(&mut self) {
+        // Displaying an object follows its references, so everything is logged before any is freed
+        #[cfg(feature = "debug_log_gc")]
+        for obj in self.objects.iter().filter(|obj| !obj.is_marked()) {
+            self.log.push(format!("{:p} free {obj}", &**obj));
+        }
+
+        let mut bytes_freed = 0;
+        self.objects.retain(|obj| {
+            if obj.is_marked() {
+                obj.unmark();
+                return true;
+            }
+
+            bytes_freed += size_of_obj(obj);
+            obj.free();
+            false
+        });
+        self.bytes_allocated -= bytes_freed;
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        self.objects.drain(..).for_each(ObjRef::free);
+    }
+}
+
+fn size_of_obj(obj: &Obj) -> usize {
+    let owned = match obj {
+        Obj::String(string) => string.chars.len(),
+        Obj::Function(function) => {
+            function.chunk.code.len()
+                + function.chunk.constants.len() * size_of::<Value>()
+                + function.chunk.locations.run_count() * size_of::<crate::chunk::LocationRun>()
+        }
+        Obj::Closure(closure) => closure.upvalues.len() * size_of::<ObjRef>(),
+        // Tables grow after allocation through `Heap::update_table`, which counts the growth.
+        Obj::Class(class) => class.methods.borrow().allocated_bytes(),
+        Obj::Instance(instance) => instance.fields.borrow().allocated_bytes(),
+        // A bound method only refers to its receiver and method, which are objects of their own.
+        Obj::Native(_) | Obj::Upvalue(_) | Obj::BoundMethod(_) => 0,
+    };
+    size_of::<Obj>() + owned
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::{ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjUpvalue};
+
+    #[test]
+    fn interning() {
+        let mut heap = Heap::new();
+
+        let hello = heap.intern("hello");
+        let world = heap.intern("world");
+
+        assert!(hello == heap.intern("hello"));
+        assert!(hello != world);
+        assert_eq!(hello.to_string(), "hello");
+        assert_eq!(heap.objects.len(), 2);
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let mut heap = Heap::new();
+        heap.intern("garbage");
+        let name = heap.intern("f");
+        let constant = heap.intern("constant");
+        let mut function = ObjFunction::new(Some(name));
+        function.chunk.add_constant(Value::Obj(constant));
+        let function = heap.allocate(Obj::Function(function));
+        let bytes_before = heap.bytes_allocated();
+
+        heap.collect_garbage([Value::Obj(function), Value::Number(1.0)].into_iter());
+
+        assert_eq!(heap.objects.len(), 3);
+        assert!(heap.objects.iter().all(|obj| !obj.is_marked()));
+        assert_eq!(
+            heap.bytes_allocated(),
+            bytes_before - size_of::<Obj>() - "garbage".len()
+        );
+        assert!(heap
+            .strings
+            .find_string("garbage", hash_string("garbage"))
+            .is_none());
+        assert!(heap.intern("constant") == constant);
+
+        heap.collect_garbage(std::iter::empty());
+        assert!(heap.objects.is_empty());
+        assert_eq!(heap.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn table_growth_is_counted() {
+        let mut heap = Heap::new();
+        let name = heap.intern("A");
+        let class = heap.allocate(Obj::Class(ObjClass::new(name)));
+        let instance = heap.allocate(Obj::Instance(ObjInstance::new(class)));
+        let bytes_before = heap.bytes_allocated();
+
+        let fields = &instance.as_instance().expect("instance").fields;
+        for i in 0..100 {
+            let key = heap.intern(&format!("field{i}"));
+            heap.update_table(fields, |fields| {
+                fields.set(key, Value::Number(i as f64));
+            });
+        }
+        let strings = (0..100).map(|i| size_of::<Obj>() + format!("field{i}").len()).sum::<usize>();
+        let table_bytes = fields.borrow().allocated_bytes();
+        assert!(table_bytes > 0);
+        assert_eq!(heap.bytes_allocated(), bytes_before + strings + table_bytes);
+
+        heap.collect_garbage(std::iter::empty());
+        assert!(heap.objects.is_empty());
+        assert_eq!(heap.bytes_allocated(), 0);
+    }
+
+    #[test]
+    fn collect_cycles() {
+        let mut heap = Heap::new();
+        let function = heap.allocate(Obj::Function(ObjFunction::new(None)));
+        let upvalue = heap.allocate(Obj::Upvalue(ObjUpvalue::new(0)));
+        let closure = heap.allocate(Obj::Closure(ObjClosure::new(function, vec![upvalue])));
+        // The upvalue closes over the closure that captures it.
+        if let Some(upvalue) = upvalue.as_upvalue() {
+            upvalue.location.set(Upvalue::Closed(Value::Obj(closure)));
+        }
+
+        heap.collect_garbage([Value::Obj(upvalue)].into_iter());
+        assert_eq!(heap.objects.len(), 3);
+
+        heap.collect_garbage(std::iter::empty());
+        assert!(heap.objects.is_empty());
+    }
+}
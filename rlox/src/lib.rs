@@ -1,16 +1,29 @@
 mod chunk;
 mod debug;
+mod heap;
 mod location;
+mod native;
+mod object;
 mod reporter;
+mod table;
 mod value;
 mod vm;
 
 pub use crate::chunk::Chunk;
+pub use crate::chunk::LocationTable;
 pub use crate::chunk::OpCode;
+pub use crate::chunk::VerifyError;
 pub use crate::debug::disassemble_chunk;
+pub use crate::location::Location;
 pub use crate::location::Region;
+pub use crate::native::NativeFn;
+pub use crate::native::NativeObject;
+pub use crate::native::NativeValue;
 pub use crate::reporter::DefaultReporter;
 pub use crate::reporter::Reporter;
+pub use crate::table::Table;
+pub use crate::table::TableKey;
 pub use crate::value::Value;
 pub use crate::vm::Vm;
 pub use crate::vm::InterpretResult;
+pub use crate::vm::RuntimeError;
@@ -1,14 +1,46 @@
 use std::fmt;
 
-#[derive(PartialEq, Debug)]
+/// A position in the source. Columns are zero based and measured in chars (`offset`), bytes and
+/// UTF-16 code units, the latter being what editors such as VS Code use. `index` is the absolute
+/// byte offset into the source. All of them stop at `u32::MAX` in sources too large to count.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Location {
-    pub line: u16,
-    pub offset: u16,
+    pub line: u32,
+    pub offset: u32,
+    pub byte_offset: u32,
+    pub utf16_offset: u32,
+    pub index: u32,
 }
 
 impl Location {
-    pub fn new(line: u16, offset: u16) -> Self {
-        Self { line, offset }
+    /// A location on a line of ASCII text, where all column measures agree. The absolute `index`
+    /// is not known and left as 0.
+    pub fn new(line: u32, offset: u32) -> Self {
+        Self {
+            line,
+            offset,
+            byte_offset: offset,
+            utf16_offset: offset,
+            index: 0,
+        }
+    }
+
+    /// The location following `c` when `c` starts at this location
+    pub(crate) fn advanced_past(self, c: char) -> Self {
+        let index = self.index.saturating_add(c.len_utf8() as u32);
+        if c == '\n' {
+            return Self {
+                index,
+                ..Self::new(self.line.saturating_add(1), 0)
+            };
+        }
+        Self {
+            line: self.line,
+            offset: self.offset.saturating_add(1),
+            byte_offset: self.byte_offset.saturating_add(c.len_utf8() as u32),
+            utf16_offset: self.utf16_offset.saturating_add(c.len_utf16() as u32),
+            index,
+        }
     }
 }
 
@@ -18,34 +50,22 @@ impl fmt::Display for Location {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Region {
     pub start: Location,
     pub end: Location,
 }
 
 impl Region {
-    pub fn new(start_line: u16, start: u16, end_line: u16, end: u16) -> Self {
+    pub fn new(start_line: u32, start: u32, end_line: u32, end: u32) -> Self {
         Self {
-            start: Location {
-                line: start_line,
-                offset: start,
-            },
-            end: Location {
-                line: end_line,
-                offset: end,
-            },
+            start: Location::new(start_line, start),
+            end: Location::new(end_line, end),
         }
     }
 
-    pub fn new_single_line(line: u16, start: u16, end: u16) -> Self {
-        Self {
-            start: Location {
-                line,
-                offset: start,
-            },
-            end: Location { line, offset: end },
-        }
+    pub fn new_single_line(line: u32, start: u32, end: u32) -> Self {
+        Self::new(line, start, line, end)
     }
 
     pub fn has_same_line(&self, other: &Self) -> bool {
@@ -49,6 +49,6 @@ fn run_file(filepath: &str) {
     match vm.interpret(&contents.unwrap()) {
         InterpretResult::Ok => process::exit(0),
         InterpretResult::CompileError => process::exit(65),
-        InterpretResult::RuntimeError => process::exit(70),
+        InterpretResult::RuntimeError | InterpretResult::InvalidChunk(_) => process::exit(70),
     }
 }
@@ -0,0 +1,63 @@
+use crate::object::ObjRef;
+use crate::value::Value;
+use crate::vm::RuntimeError;
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A host function callable from Lox. Arguments borrow from the VM for the duration of the call,
+/// so they cannot outlive it; a native may return one of them or a new value.
+pub type NativeFn = for<'a> fn(&[NativeValue<'a>]) -> Result<NativeValue<'a>, RuntimeError>;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NativeValue<'a> {
+    Bool(bool),
+    Nil,
+    Number(f64),
+    String(Cow<'a, str>),
+    Object(NativeObject<'a>),
+}
+
+impl<'a> NativeValue<'a> {
+    pub(crate) fn from_value(value: &'a Value) -> Self {
+        match value {
+            Value::Bool(value) => NativeValue::Bool(*value),
+            Value::Nil => NativeValue::Nil,
+            Value::Number(value) => NativeValue::Number(*value),
+            Value::Obj(obj) => match obj.as_string() {
+                Some(string) => NativeValue::String(Cow::Borrowed(&string.chars)),
+                None => NativeValue::Object(NativeObject {
+                    obj: *obj,
+                    marker: PhantomData,
+                }),
+            },
+        }
+    }
+}
+
+/// Any object other than a string, such as a function or an instance, passed to a native.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NativeObject<'a> {
+    pub(crate) obj: ObjRef,
+    marker: PhantomData<&'a ()>,
+}
+
+impl fmt::Display for NativeObject<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.obj)
+    }
+}
+
+impl fmt::Debug for NativeObject<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeObject({})", self.obj)
+    }
+}
+
+pub fn clock<'a>(_args: &[NativeValue<'a>]) -> Result<NativeValue<'a>, RuntimeError> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| NativeValue::Number(duration.as_secs_f64()))
+        .map_err(|_| RuntimeError::new("System clock is before the Unix epoch"))
+}
@@ -0,0 +1,277 @@
+use crate::chunk::Chunk;
+use crate::native::NativeFn;
+use crate::table::Table;
+use crate::value::Value;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+pub enum Obj {
+    String(ObjString),
+    Function(ObjFunction),
+    Native(ObjNative),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Obj::String(string) => write!(f, "{}", string.chars),
+            Obj::Function(function) => match function.name {
+                Some(name) => write!(f, "<fn {name}>"),
+                None => write!(f, "<script>"),
+            },
+            Obj::Native(_) => write!(f, "<native fn>"),
+            Obj::Closure(closure) => write!(f, "{}", closure.function),
+            Obj::Upvalue(_) => write!(f, "upvalue"),
+            Obj::Class(class) => write!(f, "{}", class.name),
+            Obj::Instance(instance) => write!(f, "{} instance", instance.class),
+            Obj::BoundMethod(bound_method) => write!(f, "{}", bound_method.method),
+        }
+    }
+}
+
+pub struct ObjString {
+    pub chars: Box<str>,
+    pub hash: u32,
+}
+
+impl ObjString {
+    pub fn new(chars: &str, hash: u32) -> Self {
+        Self {
+            chars: chars.into(),
+            hash,
+        }
+    }
+}
+
+pub struct ObjFunction {
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    pub name: Option<ObjRef>,
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<ObjRef>) -> Self {
+        Self {
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+        }
+    }
+}
+
+pub struct ObjNative {
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(arity: usize, function: NativeFn) -> Self {
+        Self { arity, function }
+    }
+}
+
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+impl ObjClosure {
+    pub fn new(function: ObjRef, upvalues: Vec<ObjRef>) -> Self {
+        Self { function, upvalues }
+    }
+}
+
+// An upvalue is open while the captured variable still lives on the VM stack and closed once
+// the variable goes out of scope, at which point the value moves into the upvalue itself.
+#[derive(Clone, Copy)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct ObjUpvalue {
+    pub location: Cell<Upvalue>,
+}
+
+impl ObjUpvalue {
+    pub fn new(slot: usize) -> Self {
+        Self {
+            location: Cell::new(Upvalue::Open(slot)),
+        }
+    }
+}
+
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub methods: RefCell<Table<ObjRef, Value>>,
+}
+
+impl ObjClass {
+    pub fn new(name: ObjRef) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(Table::new()),
+        }
+    }
+}
+
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: RefCell<Table<ObjRef, Value>>,
+}
+
+impl ObjInstance {
+    pub fn new(class: ObjRef) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(Table::new()),
+        }
+    }
+}
+
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+impl ObjBoundMethod {
+    pub fn new(receiver: Value, method: ObjRef) -> Self {
+        Self { receiver, method }
+    }
+}
+
+pub fn hash_string(chars: &str) -> u32 {
+    hash_bytes(chars.as_bytes())
+}
+
+// FNV-1a
+pub fn hash_bytes(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(2166136261u32, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(16777619)
+    })
+}
+
+struct GcBox {
+    is_marked: Cell<bool>,
+    obj: Obj,
+}
+
+#[derive(Clone, Copy)]
+pub struct ObjRef(NonNull<GcBox>);
+
+impl ObjRef {
+    pub(crate) fn allocate(obj: Obj) -> Self {
+        let gc_box = GcBox {
+            is_marked: Cell::new(false),
+            obj,
+        };
+        Self(NonNull::from(Box::leak(Box::new(gc_box))))
+    }
+
+    /// The address of the object, which identifies it for as long as it is alive
+    pub(crate) fn address(self) -> usize {
+        self.0.as_ptr() as usize
+    }
+
+    pub(crate) fn free(self) {
+        // DANGER!
+        // Only the heap frees objects, once, after they can no longer be reached.
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+
+    /// Returns `true` if the object was not already marked.
+    pub(crate) fn mark(&self) -> bool {
+        !self.gc_box().is_marked.replace(true)
+    }
+
+    pub(crate) fn unmark(&self) {
+        self.gc_box().is_marked.set(false);
+    }
+
+    pub(crate) fn is_marked(&self) -> bool {
+        self.gc_box().is_marked.get()
+    }
+
+    fn gc_box(&self) -> &GcBox {
+        // DANGER!
+        // The heap owns every object and only frees those that can no longer be reached.
+        unsafe { self.0.as_ref() }
+    }
+
+    pub(crate) fn as_string(&self) -> Option<&ObjString> {
+        match self.deref() {
+            Obj::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_function(&self) -> Option<&ObjFunction> {
+        match self.deref() {
+            Obj::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_closure(&self) -> Option<&ObjClosure> {
+        match self.deref() {
+            Obj::Closure(closure) => Some(closure),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        match self.deref() {
+            Obj::Upvalue(upvalue) => Some(upvalue),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_class(&self) -> Option<&ObjClass> {
+        match self.deref() {
+            Obj::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_instance(&self) -> Option<&ObjInstance> {
+        match self.deref() {
+            Obj::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+}
+
+impl Deref for ObjRef {
+    type Target = Obj;
+
+    fn deref(&self) -> &Obj {
+        &self.gc_box().obj
+    }
+}
+
+impl PartialEq for ObjRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl fmt::Display for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.deref())
+    }
+}
+
+impl fmt::Debug for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjRef({})", self.deref())
+    }
+}
@@ -0,0 +1,329 @@
+use crate::object::ObjRef;
+
+const TABLE_MAX_LOAD: f64 = 0.75;
+const TABLE_MIN_CAPACITY: usize = 8;
+
+pub trait TableKey: Copy + PartialEq {
+    fn hash_code(&self) -> u32;
+}
+
+impl TableKey for ObjRef {
+    fn hash_code(&self) -> u32 {
+        self.as_string().map_or(0, |string| string.hash)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Entry<K, V> {
+    Empty,
+    Tombstone,
+    Occupied(K, V),
+}
+
+// Open addressing with linear probing. Deleted entries are replaced with tombstones so that
+// probe sequences stay intact; tombstones count towards the load factor until the table grows.
+#[derive(Clone)]
+pub struct Table<K, V> {
+    // Occupied entries plus tombstones, for the load factor
+    count: usize,
+    // Occupied entries only
+    len: usize,
+    entries: Vec<Entry<K, V>>,
+}
+
+impl<K: TableKey, V: Copy> Default for Table<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: TableKey, V: Copy> Table<K, V> {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            len: 0,
+            entries: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: K) -> Option<V> {
+        if self.count == 0 {
+            return None;
+        }
+        match self.entries[find_entry(&self.entries, key)] {
+            Entry::Occupied(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the key was not already in the table.
+    pub fn set(&mut self, key: K, value: V) -> bool {
+        if (self.count + 1) as f64 > self.entries.len() as f64 * TABLE_MAX_LOAD {
+            self.adjust_capacity(grow_capacity(self.entries.len()));
+        }
+
+        let index = find_entry(&self.entries, key);
+        let entry = &mut self.entries[index];
+        let is_new_key = !matches!(entry, Entry::Occupied(..));
+        if matches!(entry, Entry::Empty) {
+            self.count += 1;
+        }
+        if is_new_key {
+            self.len += 1;
+        }
+        *entry = Entry::Occupied(key, value);
+        is_new_key
+    }
+
+    /// Returns `true` if the key was in the table.
+    pub fn delete(&mut self, key: K) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let index = find_entry(&self.entries, key);
+        let entry = &mut self.entries[index];
+        if !matches!(entry, Entry::Occupied(..)) {
+            return false;
+        }
+        *entry = Entry::Tombstone;
+        self.len -= 1;
+        true
+    }
+
+    /// Replaces every entry for which `predicate` returns `false` with a tombstone.
+    pub fn retain(&mut self, predicate: impl Fn(K, V) -> bool) {
+        for entry in self.entries.iter_mut() {
+            if let Entry::Occupied(key, value) = entry {
+                if !predicate(*key, *value) {
+                    *entry = Entry::Tombstone;
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+
+    pub fn add_all(&self, to: &mut Self) {
+        self.iter().for_each(|(key, value)| {
+            to.set(key, value);
+        });
+    }
+
+    pub fn find(&self, hash: u32, predicate: impl Fn(K) -> bool) -> Option<K> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let capacity = self.entries.len();
+        let mut index = hash as usize & (capacity - 1);
+        loop {
+            match self.entries[index] {
+                Entry::Empty => return None,
+                Entry::Occupied(key, _) if key.hash_code() == hash && predicate(key) => {
+                    return Some(key)
+                }
+                _ => (),
+            }
+            index = (index + 1) & (capacity - 1);
+        }
+    }
+
+    /// Bytes held by the entries, which only grow as keys are added.
+    pub fn allocated_bytes(&self) -> usize {
+        self.entries.len() * std::mem::size_of::<Entry<K, V>>()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::Occupied(key, value) => Some((*key, *value)),
+            _ => None,
+        })
+    }
+
+    fn adjust_capacity(&mut self, capacity: usize) {
+        let old_entries = std::mem::replace(&mut self.entries, vec![Entry::Empty; capacity]);
+        self.count = 0;
+        for entry in old_entries {
+            if let Entry::Occupied(key, value) = entry {
+                let index = find_entry(&self.entries, key);
+                self.entries[index] = Entry::Occupied(key, value);
+                self.count += 1;
+            }
+        }
+    }
+}
+
+impl<V: Copy> Table<ObjRef, V> {
+    pub fn find_string(&self, chars: &str, hash: u32) -> Option<ObjRef> {
+        self.find(hash, |key| {
+            key.as_string()
+                .is_some_and(|string| &*string.chars == chars)
+        })
+    }
+}
+
+fn grow_capacity(capacity: usize) -> usize {
+    if capacity < TABLE_MIN_CAPACITY {
+        TABLE_MIN_CAPACITY
+    } else {
+        capacity * 2
+    }
+}
+
+fn find_entry<K: TableKey, V>(entries: &[Entry<K, V>], key: K) -> usize {
+    let capacity = entries.len();
+    let mut index = key.hash_code() as usize & (capacity - 1);
+    let mut tombstone = None;
+    loop {
+        match &entries[index] {
+            Entry::Empty => return tombstone.unwrap_or(index),
+            Entry::Tombstone => {
+                tombstone.get_or_insert(index);
+            }
+            Entry::Occupied(k, _) if *k == key => return index,
+            Entry::Occupied(..) => (),
+        }
+        index = (index + 1) & (capacity - 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::heap::Heap;
+    use std::collections::HashMap;
+
+    // xorshift, good enough to generate reproducible operation sequences
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct CollidingKey(u32);
+
+    impl TableKey for CollidingKey {
+        fn hash_code(&self) -> u32 {
+            self.0 % 3
+        }
+    }
+
+    #[test]
+    fn matches_hash_map() {
+        for seed in 1..=20 {
+            let mut random = Random(seed);
+            let mut heap = Heap::new();
+            let keys: Vec<ObjRef> = (0..random.below(200) + 1)
+                .map(|i| heap.intern(&format!("key{i}")))
+                .collect();
+
+            let mut table = Table::new();
+            let mut oracle = HashMap::new();
+
+            for step in 0..2_000 {
+                let key = keys[random.below(keys.len() as u64) as usize];
+                let name = key.to_string();
+                match random.below(3) {
+                    0 => {
+                        let value = random.next();
+                        assert_eq!(
+                            table.set(key, value),
+                            oracle.insert(name, value).is_none(),
+                            "set {key} (seed {seed}, step {step})"
+                        );
+                    }
+                    1 => assert_eq!(
+                        table.delete(key),
+                        oracle.remove(&name).is_some(),
+                        "delete {key} (seed {seed}, step {step})"
+                    ),
+                    _ => assert_eq!(
+                        table.get(key),
+                        oracle.get(&name).copied(),
+                        "get {key} (seed {seed}, step {step})"
+                    ),
+                }
+                assert_eq!(table.len(), oracle.len(), "len (seed {seed}, step {step})");
+            }
+
+            for key in &keys {
+                assert_eq!(table.get(*key), oracle.get(&key.to_string()).copied());
+            }
+            assert_eq!(table.iter().count(), table.len());
+            let mut copy = Table::new();
+            table.add_all(&mut copy);
+            assert_eq!(copy.len(), oracle.len());
+            for (key, value) in table.iter() {
+                assert_eq!(copy.get(key), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn collisions_and_tombstones() {
+        let mut table = Table::new();
+        for i in 0..100 {
+            assert!(table.set(CollidingKey(i), i));
+        }
+        for i in (0..100).step_by(2) {
+            assert!(table.delete(CollidingKey(i)));
+            assert!(!table.delete(CollidingKey(i)));
+        }
+        for i in 0..100 {
+            let expected = if i % 2 == 0 { None } else { Some(i) };
+            assert_eq!(table.get(CollidingKey(i)), expected);
+        }
+        for i in (0..100).step_by(2) {
+            assert!(table.set(CollidingKey(i), i * 10));
+        }
+        assert_eq!(table.len(), 100);
+        assert_eq!(table.get(CollidingKey(42)), Some(420));
+        assert_eq!(table.find(1, |key| key.0 == 97), Some(CollidingKey(97)));
+        assert_eq!(table.find(1, |key| key.0 == 98), None);
+    }
+
+    #[test]
+    fn retain() {
+        let mut table = Table::new();
+        for i in 0..20 {
+            table.set(CollidingKey(i), i);
+        }
+        table.retain(|key, _| key.0 % 4 == 0);
+        assert_eq!(table.len(), 5);
+        assert_eq!(table.get(CollidingKey(8)), Some(8));
+        assert_eq!(table.get(CollidingKey(9)), None);
+        assert!(table.set(CollidingKey(9), 90));
+        assert_eq!(table.get(CollidingKey(9)), Some(90));
+    }
+
+    #[test]
+    fn find_string() {
+        let mut heap = Heap::new();
+        let hello = heap.intern("hello");
+        let mut table = Table::new();
+        table.set(hello, ());
+
+        assert_eq!(table.find_string("hello", hello.hash_code()), Some(hello));
+        assert_eq!(table.find_string("hellO", hello.hash_code()), None);
+        assert!(table.delete(hello));
+        assert_eq!(table.find_string("hello", hello.hash_code()), None);
+    }
+}
@@ -1,20 +1,37 @@
+use crate::object;
 use std::fmt::Display;
 use std::fmt::Formatter;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Value {
+    Bool(bool),
+    Nil,
     Number(f64),
+    Obj(object::ObjRef),
+}
+
+impl Value {
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(self, Value::Obj(obj) if obj.as_string().is_some())
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Nil => write!(f, "nil"),
             Value::Number(value) => write!(f, "{value:.2}"),
+            Value::Obj(value) => write!(f, "{value}"),
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ValueArray {
     values: Vec<Value>,
 }
@@ -36,4 +53,16 @@ impl ValueArray {
     pub fn get(&self, index: usize) -> Option<&Value> {
         self.values.get(index)
     }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter()
+    }
 }
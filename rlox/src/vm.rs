@@ -5,58 +5,165 @@ mod token;
 
 #[cfg(feature = "debug_trace_execution")]
 use crate::debug::disassemble_instruction;
-use crate::{chunk, reporter::Reporter, value};
+use crate::{chunk, heap, location, native, object, reporter::Reporter, table, value};
 
 use crate::vm::compiler::compile;
-pub use crate::vm::result::InterpretResult;
+pub use crate::vm::result::{InterpretResult, RuntimeError};
 
-const STACK_MAX: usize = 256;
+const FRAMES_MAX: usize = 64;
+const STACK_MAX: usize = FRAMES_MAX * (u8::MAX as usize + 1);
 
 macro_rules! bin_op {
-    ($stack:expr, $op:tt) => {
+    ($vm:expr, $value_type:path, $op:tt) => {
         {
-            if let Some(value::Value::Number(b)) = $stack.pop() {
-                if let Some(value::Value::Number(a)) = $stack.pop() {
+            match ($vm.peek(1), $vm.peek(0)) {
+                (Some(value::Value::Number(a)), Some(value::Value::Number(b))) => {
                     let r = a $op b;
-                    $stack.push(value::Value::Number(r));
+                    $vm.stack.truncate($vm.stack.len() - 2);
+                    $vm.stack.push($value_type(r));
                 }
+                _ => return $vm.runtime_error("Operands must be numbers"),
             }
         }
     }
 }
 
+struct CallFrame {
+    closure: object::ObjRef,
+    ip: usize,
+    instruction: usize,
+    slots: usize,
+}
+
+impl CallFrame {
+    fn function(&self) -> Option<&object::ObjFunction> {
+        self.closure.as_closure()?.function.as_function()
+    }
+
+    fn location(&self) -> location::Region {
+        self.function()
+            .and_then(|function| function.chunk.region_at(self.instruction))
+            .unwrap_or_default()
+    }
+
+    fn name(&self) -> String {
+        match self.function().and_then(|function| function.name) {
+            Some(name) => format!("{name}()"),
+            None => "script".to_string(),
+        }
+    }
+}
+
 pub struct Vm<'a> {
     reporter: &'a dyn Reporter,
-    ip: *const u8,
+    frames: Vec<CallFrame>,
+    frames_max: usize,
     stack: Vec<value::Value>,
-
-    #[cfg(feature = "debug_chunk")]
-    chunk: chunk::Chunk,
+    open_upvalues: Vec<object::ObjRef>,
+    heap: heap::Heap,
+    globals: table::Table<object::ObjRef, value::Value>,
+    init_string: object::ObjRef,
+    loaded: Option<object::ObjRef>,
 }
 
 impl<'a> Vm<'a> {
     pub fn new(reporter: &'a dyn Reporter) -> Self {
-        Self {
+        let mut heap = heap::Heap::new();
+        let init_string = heap.intern("init");
+        let mut vm = Self {
             reporter,
-            ip: std::ptr::null_mut(),
+            frames: Vec::with_capacity(FRAMES_MAX),
+            frames_max: FRAMES_MAX,
             stack: Vec::with_capacity(STACK_MAX),
-            #[cfg(feature = "debug_chunk")]
-            chunk: chunk::Chunk::new()
-        }
+            open_upvalues: vec![],
+            heap,
+            globals: table::Table::new(),
+            init_string,
+            loaded: None,
+        };
+        vm.define_native("clock", 0, native::clock);
+        vm
+    }
+
+    pub fn define_native(&mut self, name: &str, arity: usize, function: native::NativeFn) {
+        self.collect_garbage_if_needed();
+        let name = self.heap.intern(name);
+        let native = object::ObjNative::new(arity, function);
+        let native = self.heap.allocate(object::Obj::Native(native));
+        self.globals.set(name, value::Value::Obj(native));
     }
-    
+
+    pub fn set_frames_max(&mut self, frames_max: usize) {
+        self.frames_max = frames_max;
+    }
+
     pub fn interpret(&mut self, source: &str) -> InterpretResult {
-        compile(self.reporter, source);
-        //self.ip = self.chunk.code.as_ptr();
-        //self.run()
-        InterpretResult::Ok
+        let roots: Vec<value::Value> = self
+            .globals
+            .iter()
+            .flat_map(|(name, value)| [value::Value::Obj(name), value])
+            .chain(std::iter::once(value::Value::Obj(self.init_string)))
+            .chain(self.loaded.map(value::Value::Obj))
+            .collect();
+        let function = compile(self.reporter, &mut self.heap, &roots, source);
+        #[cfg(feature = "debug_log_gc")]
+        self.report_gc_log();
+        let Some(function) = function else {
+            return InterpretResult::CompileError;
+        };
+        let closure = self.heap.allocate(object::Obj::Closure(object::ObjClosure::new(function, vec![])));
+        self.start(closure)
     }
 
-    #[cfg(feature = "debug_chunk")]
-    pub fn test_interpret_chunk(&mut self, chunk: chunk::Chunk) -> InterpretResult {
-        self.chunk = chunk;
-        self.ip = self.chunk.code.as_ptr();
-        self.run()
+    pub fn run_chunk(&mut self, chunk: &chunk::Chunk) -> InterpretResult {
+        if let Err(error) = self.load_chunk(chunk) {
+            return InterpretResult::InvalidChunk(error);
+        }
+        self.run_loaded()
+    }
+
+    /// Verifies `chunk` and keeps a copy of it for `run_loaded`, which can then run it repeatedly
+    /// without verifying or copying it again.
+    pub fn load_chunk(&mut self, chunk: &chunk::Chunk) -> Result<(), chunk::VerifyError> {
+        if let Err(error) = chunk.verify() {
+            let location = chunk.region_at(error.offset()).unwrap_or_default();
+            self.reporter.add_diagnostic(&location, &error.to_string());
+            return Err(error);
+        }
+        self.collect_garbage_if_needed();
+        let mut function = object::ObjFunction::new(None);
+        function.chunk = chunk.clone();
+        function.chunk.finish_constants();
+        let function = self.heap.allocate(object::Obj::Function(function));
+        let closure = self.heap.allocate(object::Obj::Closure(object::ObjClosure::new(function, vec![])));
+        self.loaded = Some(closure);
+        Ok(())
+    }
+
+    /// Runs the chunk last passed to `load_chunk`
+    pub fn run_loaded(&mut self) -> InterpretResult {
+        match self.loaded {
+            Some(closure) => self.start(closure),
+            None => self.runtime_error("No chunk loaded"),
+        }
+    }
+
+    fn start(&mut self, closure: object::ObjRef) -> InterpretResult {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.stack.push(value::Value::Obj(closure));
+        if let Err(message) = self.call(closure, 0) {
+            return self.runtime_error(&message);
+        }
+        let result = self.run();
+        #[cfg(feature = "debug_log_gc")]
+        self.report_gc_log();
+        result
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.heap.bytes_allocated()
     }
 
     pub fn get_stack_string(&self) -> String {
@@ -68,70 +175,610 @@ impl<'a> Vm<'a> {
     }
 
     fn run(&mut self) -> InterpretResult {
-        let mut op_code: chunk::OpCode;
         loop {
-            op_code = self.read_op_code();
+            #[cfg(feature = "debug_log_gc")]
+            self.report_gc_log();
+            let Some(frame) = self.frames.last() else {
+                return InterpretResult::Ok;
+            };
+            let closure = frame.closure;
+            let Some(function) = closure
+                .as_closure()
+                .and_then(|closure| closure.function.as_function())
+            else {
+                return self.runtime_error("Can only call functions and classes");
+            };
+            let chunk = &function.chunk;
+
+            #[cfg(feature = "debug_trace_execution")]
+            if frame.ip < chunk.code.len() {
+                self.reporter.add_message(&format!("          {}", self.get_stack_string()));
+                disassemble_instruction(self.reporter, chunk, frame.ip);
+            }
+            let op_code = match self.read_op_code(chunk) {
+                Ok(op_code) => op_code,
+                Err(message) => return self.runtime_error(&message),
+            };
+            let long = op_code.is_long();
             match op_code {
-                chunk::OpCode::Negate => self.negate(),
-                chunk::OpCode::Add => bin_op!(self.stack, +),
-                chunk::OpCode::Subtract => bin_op!(self.stack, -),
-                chunk::OpCode::Multiply => bin_op!(self.stack, *),
-                chunk::OpCode::Divide => bin_op!(self.stack, /),
+                chunk::OpCode::Negate => match self.peek(0) {
+                    Some(value::Value::Number(value)) => {
+                        self.stack.pop();
+                        self.stack.push(value::Value::Number(-value));
+                    }
+                    _ => return self.runtime_error("Operand must be a number"),
+                },
+                chunk::OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Some(value::Value::Number(a)), Some(value::Value::Number(b))) => {
+                        self.stack.truncate(self.stack.len() - 2);
+                        self.stack.push(value::Value::Number(a + b));
+                    }
+                    (Some(a), Some(b)) if a.is_string() && b.is_string() => self.concatenate(a, b),
+                    _ => {
+                        return self
+                            .runtime_error("Operands must be two numbers or two strings")
+                    }
+                },
+                chunk::OpCode::Subtract => bin_op!(self, value::Value::Number, -),
+                chunk::OpCode::Multiply => bin_op!(self, value::Value::Number, *),
+                chunk::OpCode::Divide => bin_op!(self, value::Value::Number, /),
+                chunk::OpCode::Greater => bin_op!(self, value::Value::Bool, >),
+                chunk::OpCode::Less => bin_op!(self, value::Value::Bool, <),
+                chunk::OpCode::Nil => self.stack.push(value::Value::Nil),
+                chunk::OpCode::True => self.stack.push(value::Value::Bool(true)),
+                chunk::OpCode::False => self.stack.push(value::Value::Bool(false)),
+                chunk::OpCode::Not => match self.stack.pop() {
+                    Some(value) => self.stack.push(value::Value::Bool(value.is_falsey())),
+                    None => return self.runtime_error("Stack underflow"),
+                },
+                chunk::OpCode::Equal => match (self.stack.pop(), self.stack.pop()) {
+                    (Some(b), Some(a)) => self.stack.push(value::Value::Bool(a == b)),
+                    _ => return self.runtime_error("Stack underflow"),
+                },
                 chunk::OpCode::Return => {
-                    return InterpretResult::Ok;
+                    let result = self.stack.pop();
+                    let Some(frame) = self.frames.pop() else {
+                        return self.runtime_error("Invalid return");
+                    };
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return InterpretResult::Ok;
+                    }
+                    match result {
+                        Some(result) => self.stack.push(result),
+                        None => return self.runtime_error("Stack underflow"),
+                    }
+                }
+                chunk::OpCode::Print => match self.stack.pop() {
+                    Some(value) => self.reporter.add_message(&value.to_string()),
+                    None => return self.runtime_error("Stack underflow"),
+                },
+                chunk::OpCode::Pop => {
+                    self.stack.pop();
+                }
+                chunk::OpCode::DefineGlobal | chunk::OpCode::DefineGlobalLong => {
+                    let (Some(name), Some(value)) = (self.read_string(chunk, long), self.peek(0)) else {
+                        return self.runtime_error("Invalid global definition");
+                    };
+                    self.globals.set(name, value);
+                    self.stack.pop();
+                }
+                chunk::OpCode::GetGlobal | chunk::OpCode::GetGlobalLong => {
+                    let Some(name) = self.read_string(chunk, long) else {
+                        return self.runtime_error("Invalid global name");
+                    };
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            return self
+                                .runtime_error(&format!("Undefined variable '{name}'"))
+                        }
+                    }
+                }
+                chunk::OpCode::SetGlobal | chunk::OpCode::SetGlobalLong => {
+                    let (Some(name), Some(value)) = (self.read_string(chunk, long), self.peek(0)) else {
+                        return self.runtime_error("Invalid global assignment");
+                    };
+                    if self.globals.set(name, value) {
+                        self.globals.delete(name);
+                        return self.runtime_error(&format!("Undefined variable '{name}'"));
+                    }
+                }
+                chunk::OpCode::GetLocal => {
+                    let value = self
+                        .read_byte(chunk)
+                        .and_then(|slot| self.stack.get(self.slots() + slot as usize).copied());
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return self.runtime_error("Invalid local slot"),
+                    }
                 }
-                chunk::OpCode::Constant => {
-                    if let Some(constant) = self.read_constant() {
-                        self.stack.push(constant);
+                chunk::OpCode::SetLocal => {
+                    let (Some(slot), Some(value)) = (self.read_byte(chunk), self.peek(0)) else {
+                        return self.runtime_error("Invalid local slot");
+                    };
+                    let slot = self.slots() + slot as usize;
+                    match self.stack.get_mut(slot) {
+                        Some(local) => *local = value,
+                        None => return self.runtime_error("Invalid local slot"),
+                    }
+                }
+                chunk::OpCode::GetUpvalue => {
+                    let value = self.read_upvalue(chunk, closure).and_then(|upvalue| {
+                        match upvalue.as_upvalue()?.location.get() {
+                            object::Upvalue::Open(slot) => self.stack.get(slot).copied(),
+                            object::Upvalue::Closed(value) => Some(value),
+                        }
+                    });
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return self.runtime_error("Invalid upvalue"),
+                    }
+                }
+                chunk::OpCode::SetUpvalue => {
+                    let (Some(upvalue), Some(value)) = (self.read_upvalue(chunk, closure), self.peek(0))
+                    else {
+                        return self.runtime_error("Invalid upvalue");
+                    };
+                    let Some(upvalue) = upvalue.as_upvalue() else {
+                        return self.runtime_error("Invalid upvalue");
+                    };
+                    match upvalue.location.get() {
+                        object::Upvalue::Open(slot) => match self.stack.get_mut(slot) {
+                            Some(variable) => *variable = value,
+                            None => return self.runtime_error("Invalid upvalue"),
+                        },
+                        object::Upvalue::Closed(_) => {
+                            upvalue.location.set(object::Upvalue::Closed(value))
+                        }
+                    }
+                }
+                chunk::OpCode::CloseUpvalue => match self.stack.len().checked_sub(1) {
+                    Some(last) => {
+                        self.close_upvalues(last);
+                        self.stack.pop();
+                    }
+                    None => return self.runtime_error("Stack underflow"),
+                },
+                chunk::OpCode::Jump => match (self.read_short(chunk), self.frames.last_mut()) {
+                    (Some(offset), Some(frame)) => frame.ip += offset as usize,
+                    _ => return self.runtime_error("Invalid jump"),
+                },
+                chunk::OpCode::JumpIfFalse => {
+                    match (self.read_short(chunk), self.peek(0), self.frames.last_mut()) {
+                        (Some(offset), Some(condition), Some(frame)) => {
+                            if condition.is_falsey() {
+                                frame.ip += offset as usize;
+                            }
+                        }
+                        _ => return self.runtime_error("Invalid jump"),
+                    }
+                }
+                chunk::OpCode::Loop => match (self.read_short(chunk), self.frames.last_mut()) {
+                    (Some(offset), Some(frame)) if offset as usize <= frame.ip => {
+                        frame.ip -= offset as usize
+                    }
+                    _ => return self.runtime_error("Invalid loop"),
+                },
+                chunk::OpCode::Call => {
+                    let Some(arg_count) = self.read_byte(chunk) else {
+                        return self.runtime_error("Invalid call");
+                    };
+                    let Some(callee) = self.peek(arg_count as usize) else {
+                        return self.runtime_error("Stack underflow");
+                    };
+                    if let Err(message) = self.call_value(callee, arg_count) {
+                        return self.runtime_error(&message);
+                    }
+                }
+                chunk::OpCode::Closure | chunk::OpCode::ClosureLong => {
+                    self.collect_garbage_if_needed();
+                    let function = match self.read_constant(chunk, long) {
+                        Some(value::Value::Obj(function)) if function.as_function().is_some() => {
+                            function
+                        }
+                        _ => return self.runtime_error("Invalid closure"),
+                    };
+                    let upvalue_count = function.as_function().map_or(0, |function| function.upvalue_count);
+                    let mut upvalues = Vec::with_capacity(upvalue_count);
+                    for _ in 0..upvalue_count {
+                        let upvalue = match (self.read_byte(chunk), self.read_byte(chunk)) {
+                            (Some(1), Some(index)) => {
+                                Some(self.capture_upvalue(self.slots() + index as usize))
+                            }
+                            (Some(0), Some(index)) => closure
+                                .as_closure()
+                                .and_then(|closure| closure.upvalues.get(index as usize).copied()),
+                            _ => None,
+                        };
+                        match upvalue {
+                            Some(upvalue) => upvalues.push(upvalue),
+                            None => return self.runtime_error("Invalid upvalue"),
+                        }
+                    }
+                    let closure = object::ObjClosure::new(function, upvalues);
+                    let closure = self.heap.allocate(object::Obj::Closure(closure));
+                    self.stack.push(value::Value::Obj(closure));
+                }
+                chunk::OpCode::Class | chunk::OpCode::ClassLong => {
+                    self.collect_garbage_if_needed();
+                    let Some(name) = self.read_string(chunk, long) else {
+                        return self.runtime_error("Invalid class name");
+                    };
+                    let class = self.heap.allocate(object::Obj::Class(object::ObjClass::new(name)));
+                    self.stack.push(value::Value::Obj(class));
+                }
+                chunk::OpCode::GetProperty | chunk::OpCode::GetPropertyLong => {
+                    let Some(name) = self.read_string(chunk, long) else {
+                        return self.runtime_error("Invalid property name");
+                    };
+                    let receiver = self.peek(0);
+                    let Some(instance) = receiver.as_ref().and_then(as_instance) else {
+                        return self.runtime_error("Only instances have properties");
+                    };
+                    let field = instance.fields.borrow().get(name);
+                    if let Some(value) = field {
+                        self.stack.pop();
+                        self.stack.push(value);
+                        continue;
+                    }
+                    let Some(class) = instance.class.as_class() else {
+                        return self.runtime_error("Invalid class");
+                    };
+                    if let Err(message) = self.bind_method(class, name) {
+                        return self.runtime_error(&message);
+                    }
+                }
+                chunk::OpCode::SetProperty | chunk::OpCode::SetPropertyLong => {
+                    let Some(name) = self.read_string(chunk, long) else {
+                        return self.runtime_error("Invalid property name");
+                    };
+                    let receiver = self.peek(1);
+                    let (Some(instance), Some(value)) = (receiver.as_ref().and_then(as_instance), self.peek(0))
+                    else {
+                        return self.runtime_error("Only instances have fields");
+                    };
+                    self.heap.update_table(&instance.fields, |fields| {
+                        fields.set(name, value);
+                    });
+                    self.stack.truncate(self.stack.len() - 2);
+                    self.stack.push(value);
+                }
+                chunk::OpCode::Method | chunk::OpCode::MethodLong => {
+                    let (name, method, class) = (self.read_string(chunk, long), self.peek(0), self.peek(1));
+                    let (Some(name), Some(method), Some(class)) =
+                        (name, method, class.as_ref().and_then(as_class))
+                    else {
+                        return self.runtime_error("Invalid method");
+                    };
+                    self.heap.update_table(&class.methods, |methods| {
+                        methods.set(name, method);
+                    });
+                    self.stack.pop();
+                }
+                chunk::OpCode::Invoke | chunk::OpCode::InvokeLong => {
+                    let (Some(name), Some(arg_count)) = (self.read_string(chunk, long), self.read_byte(chunk))
+                    else {
+                        return self.runtime_error("Invalid invoke");
+                    };
+                    if let Err(message) = self.invoke(name, arg_count) {
+                        return self.runtime_error(&message);
+                    }
+                }
+                chunk::OpCode::Inherit => {
+                    let (Some(superclass), Some(subclass)) = (self.peek(1), self.peek(0)) else {
+                        return self.runtime_error("Stack underflow");
+                    };
+                    let (Some(superclass), Some(subclass)) = (as_class(&superclass), as_class(&subclass)) else {
+                        return self.runtime_error("Superclass must be a class");
+                    };
+                    // Methods are copied down so later lookups never walk the superclass chain.
+                    self.heap
+                        .update_table(&subclass.methods, |methods| superclass.methods.borrow().add_all(methods));
+                    self.stack.pop();
+                }
+                chunk::OpCode::GetSuper | chunk::OpCode::GetSuperLong => {
+                    let (name, superclass) = (self.read_string(chunk, long), self.stack.pop());
+                    let (Some(name), Some(superclass)) = (name, superclass.as_ref().and_then(as_class)) else {
+                        return self.runtime_error("Invalid super access");
+                    };
+                    if let Err(message) = self.bind_method(superclass, name) {
+                        return self.runtime_error(&message);
+                    }
+                }
+                chunk::OpCode::SuperInvoke | chunk::OpCode::SuperInvokeLong => {
+                    let (name, arg_count) = (self.read_string(chunk, long), self.read_byte(chunk));
+                    let superclass = self.stack.pop();
+                    let (Some(name), Some(arg_count), Some(superclass)) =
+                        (name, arg_count, superclass.as_ref().and_then(as_class))
+                    else {
+                        return self.runtime_error("Invalid super invoke");
+                    };
+                    if let Err(message) = self.invoke_from_class(superclass, name, arg_count) {
+                        return self.runtime_error(&message);
+                    }
+                }
+                chunk::OpCode::Constant | chunk::OpCode::ConstantLong => {
+                    match self.read_constant(chunk, long) {
+                        Some(constant) => self.stack.push(constant),
+                        None => return self.runtime_error("Invalid constant"),
                     }
                 }
             }
         }
     }
 
-    fn negate(&mut self) {
-        if let Some(value::Value::Number(value)) = self.stack.pop() {
-            self.stack.push(value::Value::Number(-value));
+    fn call_value(&mut self, callee: value::Value, arg_count: u8) -> Result<(), String> {
+        let value::Value::Obj(obj) = callee else {
+            return Err("Can only call functions and classes".to_string());
+        };
+        match &*obj {
+            object::Obj::Closure(_) => self.call(obj, arg_count),
+            object::Obj::Native(native) => self.call_native(native, arg_count),
+            object::Obj::BoundMethod(bound_method) => {
+                let receiver = self.stack.len() - arg_count as usize - 1;
+                self.stack[receiver] = bound_method.receiver;
+                self.call(bound_method.method, arg_count)
+            }
+            object::Obj::Class(class) => {
+                self.collect_garbage_if_needed();
+                let instance = self.heap.allocate(object::Obj::Instance(object::ObjInstance::new(obj)));
+                let receiver = self.stack.len() - arg_count as usize - 1;
+                self.stack[receiver] = value::Value::Obj(instance);
+                match class.methods.borrow().get(self.init_string) {
+                    Some(value::Value::Obj(initializer)) => self.call(initializer, arg_count),
+                    _ if arg_count != 0 => Err(format!("Expected 0 arguments but got {arg_count}")),
+                    _ => Ok(()),
+                }
+            }
+            _ => Err("Can only call functions and classes".to_string()),
         }
     }
 
-    #[inline(always)]
-    fn read_byte(&mut self) -> u8 {
-        let byte: u8;
-        unsafe {
-            // DANGER!
-            byte = *self.ip;
-            self.ip = self.ip.add(1);
+    fn invoke(&mut self, name: object::ObjRef, arg_count: u8) -> Result<(), String> {
+        let receiver = self.peek(arg_count as usize);
+        let Some(instance) = receiver.as_ref().and_then(as_instance) else {
+            return Err("Only instances have methods".to_string());
+        };
+
+        let field = instance.fields.borrow().get(name);
+        if let Some(field) = field {
+            let receiver = self.stack.len() - arg_count as usize - 1;
+            self.stack[receiver] = field;
+            return self.call_value(field, arg_count);
+        }
+        match instance.class.as_class() {
+            Some(class) => self.invoke_from_class(class, name, arg_count),
+            None => Err("Only instances have methods".to_string()),
+        }
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: &object::ObjClass,
+        name: object::ObjRef,
+        arg_count: u8,
+    ) -> Result<(), String> {
+        let method = class.methods.borrow().get(name);
+        match method {
+            Some(value::Value::Obj(method)) => self.call(method, arg_count),
+            _ => Err(format!("Undefined property '{name}'")),
+        }
+    }
+
+    fn bind_method(&mut self, class: &object::ObjClass, name: object::ObjRef) -> Result<(), String> {
+        let method = class.methods.borrow().get(name);
+        let (Some(value::Value::Obj(method)), Some(receiver)) = (method, self.peek(0)) else {
+            return Err(format!("Undefined property '{name}'"));
+        };
+
+        self.collect_garbage_if_needed();
+        let bound_method = object::ObjBoundMethod::new(receiver, method);
+        let bound_method = self.heap.allocate(object::Obj::BoundMethod(bound_method));
+        self.stack.pop();
+        self.stack.push(value::Value::Obj(bound_method));
+        Ok(())
+    }
+
+    fn call(&mut self, closure: object::ObjRef, arg_count: u8) -> Result<(), String> {
+        let arity = closure
+            .as_closure()
+            .and_then(|closure| closure.function.as_function())
+            .map_or(0, |function| function.arity);
+        if arg_count as usize != arity {
+            return Err(format!("Expected {arity} arguments but got {arg_count}"));
+        }
+        if self.frames.len() >= self.frames_max {
+            return Err("Stack overflow".to_string());
         }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            instruction: 0,
+            slots: self.stack.len() - arg_count as usize - 1,
+        });
+        Ok(())
+    }
+
+    fn call_native(&mut self, native: &object::ObjNative, arg_count: u8) -> Result<(), String> {
+        if arg_count as usize != native.arity {
+            return Err(format!("Expected {} arguments but got {arg_count}", native.arity));
+        }
+
+        let args_start = self.stack.len() - arg_count as usize;
+        let args: Vec<native::NativeValue> =
+            self.stack[args_start..].iter().map(native::NativeValue::from_value).collect();
+        let result = match (native.function)(&args).map_err(|error| error.message)? {
+            native::NativeValue::Bool(value) => value::Value::Bool(value),
+            native::NativeValue::Nil => value::Value::Nil,
+            native::NativeValue::Number(value) => value::Value::Number(value),
+            native::NativeValue::Object(object) => value::Value::Obj(object.obj),
+            native::NativeValue::String(chars) => {
+                // The arguments stay on the stack, and so reachable, until the result is interned
+                let chars = chars.into_owned();
+                self.collect_garbage_if_needed();
+                value::Value::Obj(self.heap.intern(&chars))
+            }
+        };
+        self.stack.truncate(args_start - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> object::ObjRef {
+        // Open upvalues are kept sorted by stack slot so closing them can stop early.
+        let index = self
+            .open_upvalues
+            .partition_point(|upvalue| open_slot(*upvalue) < Some(slot));
+        if let Some(upvalue) = self.open_upvalues.get(index) {
+            if open_slot(*upvalue) == Some(slot) {
+                return *upvalue;
+            }
+        }
+
+        let upvalue = self
+            .heap
+            .allocate(object::Obj::Upvalue(object::ObjUpvalue::new(slot)));
+        self.open_upvalues.insert(index, upvalue);
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            match (open_slot(upvalue), upvalue.as_upvalue()) {
+                (Some(slot), Some(upvalue)) if slot >= last => {
+                    let value = self.stack.get(slot).copied().unwrap_or(value::Value::Nil);
+                    upvalue.location.set(object::Upvalue::Closed(value));
+                    self.open_upvalues.pop();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn concatenate(&mut self, a: value::Value, b: value::Value) {
+        self.collect_garbage_if_needed();
+        let result = self.heap.intern(&format!("{a}{b}"));
+        self.stack.truncate(self.stack.len() - 2);
+        self.stack.push(value::Value::Obj(result));
+    }
+
+    #[cfg(feature = "debug_log_gc")]
+    fn report_gc_log(&mut self) {
+        self.heap.take_log().iter().for_each(|line| self.reporter.add_message(line));
+    }
+
+    fn collect_garbage_if_needed(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+
+        let roots = self
+            .stack
+            .iter()
+            .copied()
+            .chain(self.frames.iter().map(|frame| value::Value::Obj(frame.closure)))
+            .chain(self.open_upvalues.iter().map(|upvalue| value::Value::Obj(*upvalue)))
+            .chain(self.globals.iter().flat_map(|(name, value)| [value::Value::Obj(name), value]))
+            .chain(std::iter::once(value::Value::Obj(self.init_string)))
+            .chain(self.loaded.map(value::Value::Obj));
+        self.heap.collect_garbage(roots);
+    }
+
+    fn peek(&self, distance: usize) -> Option<value::Value> {
+        self.stack.iter().rev().nth(distance).copied()
+    }
+
+    fn slots(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.slots)
+    }
+
+    fn runtime_error(&mut self, message: &str) -> InterpretResult {
+        let location = self.frames.last().map(CallFrame::location).unwrap_or_default();
+        self.reporter.add_diagnostic(&location, message);
+        for frame in self.frames.iter().rev() {
+            self.reporter
+                .add_diagnostic(&frame.location(), &format!("in {}", frame.name()));
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        InterpretResult::RuntimeError
+    }
+
+    #[inline(always)]
+    fn read_byte(&mut self, chunk: &chunk::Chunk) -> Option<u8> {
+        let frame = self.frames.last_mut()?;
+        let byte = chunk.code.get(frame.ip).copied();
+        frame.ip += 1;
         byte
     }
 
     #[inline(always)]
-    fn read_constant(&mut self) -> Option<value::Value> {
-        #[cfg(feature = "debug_chunk")]
-        {
-            let index = self.read_byte() as usize;
-            return self.chunk.constants.get(index).cloned();
-        } 
-        #[cfg(not(feature = "debug_chunk"))]
-        None
+    fn read_short(&mut self, chunk: &chunk::Chunk) -> Option<u16> {
+        let frame = self.frames.last_mut()?;
+        let short = chunk.read_short(frame.ip);
+        frame.ip += 2;
+        short
     }
 
     #[inline(always)]
-    fn read_op_code(&mut self) -> chunk::OpCode {
-        let op_code: chunk::OpCode = unsafe {
-            // DANGER!
-            #[cfg(feature = "debug_trace_execution")]
-            {
-                #[cfg(feature = "debug_chunk")]
-                if self.chunk.has_code() {
-                    self.reporter.add_message(&format!("          {}", self.get_stack_string()));
-                    let index = self.ip.offset_from(self.chunk.code.as_ptr());
-                    disassemble_instruction(self.reporter, &self.chunk, index as usize);
-                }
-            }
-            std::mem::transmute(self.read_byte())
+    fn read_constant(&mut self, chunk: &chunk::Chunk, long: bool) -> Option<value::Value> {
+        let frame = self.frames.last_mut()?;
+        let index = chunk.read_index(frame.ip, long);
+        frame.ip += if long { 3 } else { 1 };
+        chunk.constants.get(index?).copied()
+    }
+
+    #[inline(always)]
+    fn read_upvalue(
+        &mut self,
+        chunk: &chunk::Chunk,
+        closure: object::ObjRef,
+    ) -> Option<object::ObjRef> {
+        let slot = self.read_byte(chunk)? as usize;
+        closure.as_closure()?.upvalues.get(slot).copied()
+    }
+
+    #[inline(always)]
+    fn read_string(&mut self, chunk: &chunk::Chunk, long: bool) -> Option<object::ObjRef> {
+        match self.read_constant(chunk, long)? {
+            value::Value::Obj(obj) if obj.as_string().is_some() => Some(obj),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    fn read_op_code(&mut self, chunk: &chunk::Chunk) -> Result<chunk::OpCode, String> {
+        let Some(byte) = self.read_byte(chunk) else {
+            return Err("Unexpected end of byte code".to_string());
         };
-        op_code
+        if let Some(frame) = self.frames.last_mut() {
+            frame.instruction = frame.ip - 1;
+        }
+        chunk::OpCode::try_from(byte).map_err(|byte| format!("Unknown op_code {byte}"))
+    }
+}
+
+fn as_class(value: &value::Value) -> Option<&object::ObjClass> {
+    match value {
+        value::Value::Obj(obj) => obj.as_class(),
+        _ => None,
+    }
+}
+
+fn as_instance(value: &value::Value) -> Option<&object::ObjInstance> {
+    match value {
+        value::Value::Obj(obj) => obj.as_instance(),
+        _ => None,
+    }
+}
+
+fn open_slot(upvalue: object::ObjRef) -> Option<usize> {
+    match upvalue.as_upvalue()?.location.get() {
+        object::Upvalue::Open(slot) => Some(slot),
+        object::Upvalue::Closed(_) => None,
     }
 }
@@ -1,25 +1,1404 @@
+#[cfg(feature = "debug_chunk")]
+use crate::debug::disassemble_chunk;
 use crate::reporter::Reporter;
-use crate::vm::{scanner::Scanner, token::TokenType};
+use crate::vm::{
+    scanner::{self, Scanner},
+    token::{Token, TokenType},
+};
+use crate::{chunk, heap, location, object, value};
 
-pub fn compile(reporter: &dyn Reporter, source: &str) {
-    let mut scanner = Scanner::new(source);
-    let mut line: Option<u16> = None;
-    loop {
-        let token = scanner.scan_token();
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call => Precedence::Primary,
+            Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn<'a> = fn(&mut Compiler<'a>, bool);
+
+struct ParseRule<'a> {
+    prefix: Option<ParseFn<'a>>,
+    infix: Option<ParseFn<'a>>,
+    precedence: Precedence,
+}
+
+impl<'a> ParseRule<'a> {
+    fn new(prefix: Option<ParseFn<'a>>, infix: Option<ParseFn<'a>>, precedence: Precedence) -> Self {
+        Self {
+            prefix,
+            infix,
+            precedence,
+        }
+    }
+}
+
+fn get_rule<'a>(token_type: TokenType) -> ParseRule<'a> {
+    match token_type {
+        TokenType::LeftParen => ParseRule::new(Some(Compiler::grouping), Some(Compiler::call), Precedence::Call),
+        TokenType::Dot => ParseRule::new(None, Some(Compiler::dot), Precedence::Call),
+        TokenType::Minus => ParseRule::new(Some(Compiler::unary), Some(Compiler::binary), Precedence::Term),
+        TokenType::Plus => ParseRule::new(None, Some(Compiler::binary), Precedence::Term),
+        TokenType::Slash => ParseRule::new(None, Some(Compiler::binary), Precedence::Factor),
+        TokenType::Star => ParseRule::new(None, Some(Compiler::binary), Precedence::Factor),
+        TokenType::Bang => ParseRule::new(Some(Compiler::unary), None, Precedence::None),
+        TokenType::BangEqual => ParseRule::new(None, Some(Compiler::binary), Precedence::Equality),
+        TokenType::EqualEqual => ParseRule::new(None, Some(Compiler::binary), Precedence::Equality),
+        TokenType::Greater => ParseRule::new(None, Some(Compiler::binary), Precedence::Comparison),
+        TokenType::GreaterEqual => ParseRule::new(None, Some(Compiler::binary), Precedence::Comparison),
+        TokenType::Less => ParseRule::new(None, Some(Compiler::binary), Precedence::Comparison),
+        TokenType::LessEqual => ParseRule::new(None, Some(Compiler::binary), Precedence::Comparison),
+        TokenType::Identifier => ParseRule::new(Some(Compiler::variable), None, Precedence::None),
+        TokenType::Number => ParseRule::new(Some(Compiler::number), None, Precedence::None),
+        TokenType::String => ParseRule::new(Some(Compiler::string), None, Precedence::None),
+        TokenType::And => ParseRule::new(None, Some(Compiler::and), Precedence::And),
+        TokenType::Or => ParseRule::new(None, Some(Compiler::or), Precedence::Or),
+        TokenType::False => ParseRule::new(Some(Compiler::literal), None, Precedence::None),
+        TokenType::Nil => ParseRule::new(Some(Compiler::literal), None, Precedence::None),
+        TokenType::Super => ParseRule::new(Some(Compiler::super_), None, Precedence::None),
+        TokenType::This => ParseRule::new(Some(Compiler::this), None, Precedence::None),
+        TokenType::True => ParseRule::new(Some(Compiler::literal), None, Precedence::None),
+        _ => ParseRule::new(None, None, Precedence::None),
+    }
+}
+
+const LOCALS_MAX: usize = u8::MAX as usize + 1;
+const PARAMETERS_MAX: usize = u8::MAX as usize;
+const UPVALUES_MAX: usize = u8::MAX as usize + 1;
+
+struct Local<'a> {
+    name: Token<'a>,
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+struct FunctionState<'a> {
+    function: object::ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local<'a>>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl<'a> FunctionState<'a> {
+    fn new(function_type: FunctionType, name: Option<object::ObjRef>) -> Self {
+        let mut locals = Vec::with_capacity(LOCALS_MAX);
+        // Slot zero holds the function being called, or the receiver for methods.
+        let slot_zero = match function_type {
+            FunctionType::Initializer | FunctionType::Method => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+        locals.push(Local {
+            name: Token::new(TokenType::Identifier, slot_zero, location::Region::default()),
+            depth: Some(0),
+            is_captured: false,
+        });
+        Self {
+            function: object::ObjFunction::new(name),
+            function_type,
+            locals,
+            upvalues: vec![],
+            scope_depth: 0,
+        }
+    }
+}
+
+struct Compiler<'a> {
+    reporter: &'a dyn Reporter,
+    heap: &'a mut heap::Heap,
+    roots: &'a [value::Value],
+    scanner: Scanner<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    had_error: bool,
+    panic_mode: bool,
+    state: FunctionState<'a>,
+    enclosing: Vec<FunctionState<'a>>,
+    classes: Vec<ClassState>,
+}
+
+pub fn compile(
+    reporter: &dyn Reporter,
+    heap: &mut heap::Heap,
+    roots: &[value::Value],
+    source: &str,
+) -> Option<object::ObjRef> {
+    let mut compiler = Compiler::new(reporter, heap, roots, source);
+
+    compiler.advance();
+    while !compiler.match_token(TokenType::Eof) {
+        compiler.declaration();
+    }
+    let function = compiler.end_compiler().function;
+
+    if compiler.had_error {
+        None
+    } else {
+        Some(compiler.heap.allocate(object::Obj::Function(function)))
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn new(
+        reporter: &'a dyn Reporter,
+        heap: &'a mut heap::Heap,
+        roots: &'a [value::Value],
+        source: &'a str,
+    ) -> Self {
+        Self {
+            reporter,
+            heap,
+            roots,
+            scanner: Scanner::new(source),
+            current: Token::new(TokenType::Eof, "", location::Region::default()),
+            previous: Token::new(TokenType::Eof, "", location::Region::default()),
+            had_error: false,
+            panic_mode: false,
+            state: FunctionState::new(FunctionType::Script, None),
+            enclosing: vec![],
+            classes: vec![],
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+
+        loop {
+            self.current = self.scanner.scan_token();
+            match self.current.token_type {
+                TokenType::DocComment => continue,
+                TokenType::Error => self.error_at_current(self.current.lexeme),
+                _ => break,
+            }
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) {
+        if self.current.token_type == token_type {
+            self.advance();
+            return;
+        }
+        self.error_at_current(message);
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    fn declaration(&mut self) {
+        if self.match_token(TokenType::Class) {
+            self.class_declaration();
+        } else if self.match_token(TokenType::Fun) {
+            self.fun_declaration();
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(TokenType::Identifier, "Expect class name");
+        let class_name = self.previous.clone();
+        let name_constant = self.identifier_constant(&class_name);
+        self.declare_variable();
+
+        self.emit_constant_op(chunk::OpCode::Class, name_constant, class_name.location);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState { has_superclass: false });
+
+        if self.match_token(TokenType::Less) {
+            self.consume(TokenType::Identifier, "Expect superclass name");
+            let superclass = self.previous.clone();
+            self.variable(false);
+            if superclass.lexeme == class_name.lexeme {
+                self.error("A class can't inherit from itself");
+            }
+
+            self.begin_scope();
+            self.add_local(Token::new(TokenType::Identifier, "super", superclass.location));
+            self.define_variable(0);
+
+            self.named_variable(&class_name, false);
+            self.emit_op_code(chunk::OpCode::Inherit, superclass.location);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(&class_name, false);
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body");
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.method();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body");
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+
+        if self.classes.pop().is_some_and(|class| class.has_superclass) {
+            self.end_scope();
+        }
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name");
+        let name = self.previous.clone();
+        let constant = self.identifier_constant(&name);
+        let function_type = if name.lexeme == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(function_type);
+        self.emit_constant_op(chunk::OpCode::Method, constant, name.location);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name");
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.intern(self.previous.lexeme);
+        let state = FunctionState::new(function_type, Some(name));
+        let enclosing = std::mem::replace(&mut self.state, state);
+        self.enclosing.push(enclosing);
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name");
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.state.function.arity += 1;
+                if self.state.function.arity > PARAMETERS_MAX {
+                    self.error_at_current("Can't have more than 255 parameters");
+                }
+                let constant = self.parse_variable("Expect parameter name");
+                self.define_variable(constant);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body");
+        self.block();
+
+        let state = self.end_compiler();
+        let function = self.heap.allocate(object::Obj::Function(state.function));
+        let constant = self.make_constant(value::Value::Obj(function));
+        let location = self.previous.location;
+        self.emit_constant_op(chunk::OpCode::Closure, constant, location);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8, location);
+            self.emit_byte(upvalue.index, location);
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name");
+
+        if self.match_token(TokenType::Equal) {
+            self.expression();
+        } else {
+            self.emit_op_code(chunk::OpCode::Nil, self.previous.location);
+        }
+        self.consume(TokenType::Semicolon, "Expect ';' after variable declaration");
+
+        self.define_variable(global);
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TokenType::Print) {
+            self.print_statement();
+        } else if self.match_token(TokenType::For) {
+            self.for_statement();
+        } else if self.match_token(TokenType::If) {
+            self.if_statement();
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement();
+        } else if self.match_token(TokenType::While) {
+            self.while_statement();
+        } else if self.match_token(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.declaration();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block");
+    }
+
+    fn begin_scope(&mut self) {
+        self.state.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state.scope_depth -= 1;
+
+        let location = self.previous.location;
+        while self
+            .state
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_none_or(|depth| depth > self.state.scope_depth))
+        {
+            let op_code = match self.state.locals.pop() {
+                Some(local) if local.is_captured => chunk::OpCode::CloseUpvalue,
+                _ => chunk::OpCode::Pop,
+            };
+            self.emit_op_code(op_code, location);
+        }
+    }
+
+    fn print_statement(&mut self) {
+        let location = self.previous.location;
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after value");
+        self.emit_op_code(chunk::OpCode::Print, location);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'");
+        if self.match_token(TokenType::Semicolon) {
+            // No initializer.
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TokenType::Semicolon) {
+            self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after loop condition");
+
+            exit_jump = Some(self.emit_jump(chunk::OpCode::JumpIfFalse));
+            self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+        }
+
+        if !self.match_token(TokenType::RightParen) {
+            let body_jump = self.emit_jump(chunk::OpCode::Jump);
+            let increment_start = self.current_chunk().code.len();
+            self.expression();
+            self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+        }
+
+        self.end_scope();
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition");
+
+        let then_jump = self.emit_jump(chunk::OpCode::JumpIfFalse);
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+        self.statement();
+
+        let else_jump = self.emit_jump(chunk::OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+
+        if self.match_token(TokenType::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn return_statement(&mut self) {
+        let location = self.previous.location;
+        if self.state.function_type == FunctionType::Script {
+            self.error("Can't return from top-level code");
+        }
+
+        if self.match_token(TokenType::Semicolon) {
+            self.emit_return();
+        } else {
+            if self.state.function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer");
+            }
+            self.expression();
+            self.consume(TokenType::Semicolon, "Expect ';' after return value");
+            self.emit_op_code(chunk::OpCode::Return, location);
+        }
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition");
+
+        let exit_jump = self.emit_jump(chunk::OpCode::JumpIfFalse);
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after expression");
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.current.token_type != TokenType::Eof {
+            if self.previous.token_type == TokenType::Semicolon {
+                return;
+            }
+            match self.current.token_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => (),
+            }
+            self.advance();
+        }
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let Some(prefix_rule) = get_rule(self.previous.token_type).prefix else {
+            self.error("Expect expression");
+            return;
+        };
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix_rule(self, can_assign);
+
+        while precedence <= get_rule(self.current.token_type).precedence {
+            self.advance();
+            if let Some(infix_rule) = get_rule(self.previous.token_type).infix {
+                infix_rule(self, can_assign);
+            }
+        }
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.error("Invalid assignment target");
+        }
+    }
+
+    fn parse_variable(&mut self, message: &str) -> usize {
+        self.consume(TokenType::Identifier, message);
+
+        self.declare_variable();
+        if self.state.scope_depth > 0 {
+            return 0;
+        }
+
+        let name = self.previous.clone();
+        self.identifier_constant(&name)
+    }
+
+    fn declare_variable(&mut self) {
+        if self.state.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous.clone();
+        let duplicate = self
+            .state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= self.state.scope_depth))
+            .any(|local| local.name.lexeme == name.lexeme);
+        if duplicate {
+            self.error("Already a variable with this name in this scope");
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: Token<'a>) {
+        if self.state.locals.len() == LOCALS_MAX {
+            self.error("Too many local variables in function");
+            return;
+        }
+        self.state.locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.state.scope_depth == 0 {
+            return;
+        }
+        let scope_depth = self.state.scope_depth;
+        if let Some(local) = self.state.locals.last_mut() {
+            local.depth = Some(scope_depth);
+        }
+    }
+
+    // Level zero is the outermost script; the function currently being compiled is at
+    // `self.enclosing.len()`.
+    fn state_at(&mut self, level: usize) -> &mut FunctionState<'a> {
+        if level == self.enclosing.len() {
+            &mut self.state
+        } else {
+            &mut self.enclosing[level]
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Token) -> Option<u8> {
+        self.resolve_local_at(self.enclosing.len(), name)
+    }
+
+    fn resolve_local_at(&mut self, level: usize, name: &Token) -> Option<u8> {
+        let (slot, local) = self
+            .state_at(level)
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.lexeme == name.lexeme)?;
+        if local.depth.is_none() {
+            self.error("Can't read local variable in its own initializer");
+        }
+        Some(slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, name: &Token) -> Option<u8> {
+        self.resolve_upvalue_at(self.enclosing.len(), name)
+    }
+
+    fn resolve_upvalue_at(&mut self, level: usize, name: &Token) -> Option<u8> {
+        let enclosing = level.checked_sub(1)?;
+
+        if let Some(local) = self.resolve_local_at(enclosing, name) {
+            self.state_at(enclosing).locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(level, local, true));
+        }
+
+        let upvalue = self.resolve_upvalue_at(enclosing, name)?;
+        Some(self.add_upvalue(level, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, level: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let state = self.state_at(level);
+        if let Some(existing) = state.upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+
+        if state.upvalues.len() == UPVALUES_MAX {
+            self.error("Too many closure variables in function");
+            return 0;
+        }
+        state.upvalues.push(upvalue);
+        state.function.upvalue_count = state.upvalues.len();
+        (state.upvalues.len() - 1) as u8
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> usize {
+        let string = self.intern(name.lexeme);
+        self.make_constant(value::Value::Obj(string))
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        if self.state.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        let location = self.previous.location;
+        self.emit_constant_op(chunk::OpCode::DefineGlobal, global, location);
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.previous.clone();
+        self.named_variable(&name, can_assign);
+    }
+
+    fn named_variable(&mut self, name: &Token, can_assign: bool) {
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(name) {
+            (chunk::OpCode::GetLocal, chunk::OpCode::SetLocal, slot as usize)
+        } else if let Some(upvalue) = self.resolve_upvalue(name) {
+            (chunk::OpCode::GetUpvalue, chunk::OpCode::SetUpvalue, upvalue as usize)
+        } else {
+            (
+                chunk::OpCode::GetGlobal,
+                chunk::OpCode::SetGlobal,
+                self.identifier_constant(name),
+            )
+        };
+
+        let op_code = if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            set_op
+        } else {
+            get_op
+        };
+        if op_code.long_form().is_some() {
+            self.emit_constant_op(op_code, arg, name.location);
+        } else {
+            self.emit_op_code(op_code, name.location);
+            self.emit_byte(arg as u8, name.location);
+        }
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let location = self.previous.location;
+        let arg_count = self.argument_list();
+        self.emit_op_code(chunk::OpCode::Call, location);
+        self.emit_byte(arg_count, location);
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(TokenType::Identifier, "Expect property name after '.'");
+        let name = self.previous.clone();
+        let constant = self.identifier_constant(&name);
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.expression();
+            self.emit_constant_op(chunk::OpCode::SetProperty, constant, name.location);
+        } else if self.match_token(TokenType::LeftParen) {
+            let arg_count = self.argument_list();
+            self.emit_constant_op(chunk::OpCode::Invoke, constant, name.location);
+            self.emit_byte(arg_count, name.location);
+        } else {
+            self.emit_constant_op(chunk::OpCode::GetProperty, constant, name.location);
+        }
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == PARAMETERS_MAX {
+                    self.error("Can't have more than 255 arguments");
+                }
+                arg_count += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments");
+        arg_count.min(PARAMETERS_MAX) as u8
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(chunk::OpCode::JumpIfFalse);
+
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(chunk::OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(chunk::OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op_code(chunk::OpCode::Pop, self.previous.location);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        match scanner::parse_number(self.previous.lexeme) {
+            Some(value) => self.emit_constant(value::Value::Number(value)),
+            None => self.error("Invalid number"),
+        }
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let lexeme = self.previous.lexeme;
+        // The scanner turns strings with malformed escapes into error tokens, so this cannot fail
+        let contents = scanner::unescape(&lexeme[1..lexeme.len() - 1]).expect("escapes checked by the scanner");
+        let string = self.intern(&contents);
+        self.emit_constant(value::Value::Obj(string));
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        let op_code = match self.previous.token_type {
+            TokenType::False => chunk::OpCode::False,
+            TokenType::Nil => chunk::OpCode::Nil,
+            TokenType::True => chunk::OpCode::True,
+            _ => return,
+        };
+        self.emit_op_code(op_code, self.previous.location);
+    }
+
+    fn this(&mut self, _can_assign: bool) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class");
+            return;
+        }
+        self.variable(false);
+    }
+
+    fn super_(&mut self, _can_assign: bool) {
+        let keyword = self.previous.clone();
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class"),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass")
+            }
+            _ => (),
+        }
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'");
+        self.consume(TokenType::Identifier, "Expect superclass method name");
+        let name = self.previous.clone();
+        let constant = self.identifier_constant(&name);
+
+        self.named_variable(&Token::new(TokenType::Identifier, "this", keyword.location), false);
+        let super_token = Token::new(TokenType::Identifier, "super", keyword.location);
+        if self.match_token(TokenType::LeftParen) {
+            let arg_count = self.argument_list();
+            self.named_variable(&super_token, false);
+            self.emit_constant_op(chunk::OpCode::SuperInvoke, constant, name.location);
+            self.emit_byte(arg_count, name.location);
+        } else {
+            self.named_variable(&super_token, false);
+            self.emit_constant_op(chunk::OpCode::GetSuper, constant, name.location);
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after expression");
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let operator = self.previous.clone();
+
+        self.parse_precedence(Precedence::Unary);
+
+        let op_code = match operator.token_type {
+            TokenType::Bang => chunk::OpCode::Not,
+            TokenType::Minus => chunk::OpCode::Negate,
+            _ => return,
+        };
+        self.emit_op_code(op_code, operator.location);
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let operator = self.previous.clone();
+        let rule = get_rule(operator.token_type);
+        self.parse_precedence(rule.precedence.next());
+
+        let location = operator.location;
+        match operator.token_type {
+            TokenType::BangEqual => {
+                self.emit_op_code(chunk::OpCode::Equal, location);
+                self.emit_op_code(chunk::OpCode::Not, location);
+            }
+            TokenType::EqualEqual => self.emit_op_code(chunk::OpCode::Equal, location),
+            TokenType::Greater => self.emit_op_code(chunk::OpCode::Greater, location),
+            TokenType::GreaterEqual => {
+                self.emit_op_code(chunk::OpCode::Less, location);
+                self.emit_op_code(chunk::OpCode::Not, location);
+            }
+            TokenType::Less => self.emit_op_code(chunk::OpCode::Less, location),
+            TokenType::LessEqual => {
+                self.emit_op_code(chunk::OpCode::Greater, location);
+                self.emit_op_code(chunk::OpCode::Not, location);
+            }
+            TokenType::Plus => self.emit_op_code(chunk::OpCode::Add, location),
+            TokenType::Minus => self.emit_op_code(chunk::OpCode::Subtract, location),
+            TokenType::Star => self.emit_op_code(chunk::OpCode::Multiply, location),
+            TokenType::Slash => self.emit_op_code(chunk::OpCode::Divide, location),
+            _ => (),
+        }
+    }
 
-        let mut message = "".to_string();
-        if line.is_none() || line.unwrap() != token.location.start.line {
-            line = Some(token.location.start.line);
-            message.push_str(&format!("{:04} ", line.unwrap()));
+    fn intern(&mut self, chars: &str) -> object::ObjRef {
+        self.collect_garbage_if_needed();
+        self.heap.intern(chars)
+    }
+
+    // Constants of every function still being compiled are roots, as is everything the VM keeps
+    // alive between runs.
+    fn collect_garbage_if_needed(&mut self) {
+        if !self.heap.should_collect() {
+            return;
+        }
+
+        let functions = self
+            .enclosing
+            .iter()
+            .chain(std::iter::once(&self.state))
+            .map(|state| &state.function);
+        let roots = functions
+            .flat_map(|function| {
+                let name = function.name.map(value::Value::Obj);
+                name.into_iter()
+                    .chain(function.chunk.constants.iter().copied())
+            })
+            .chain(self.roots.iter().copied());
+        self.heap.collect_garbage(roots);
+    }
+
+    fn current_chunk(&mut self) -> &mut chunk::Chunk {
+        &mut self.state.function.chunk
+    }
+
+    fn emit_op_code(&mut self, op_code: chunk::OpCode, location: location::Region) {
+        self.current_chunk().write_op_code(op_code, location);
+    }
+
+    fn emit_byte(&mut self, byte: u8, location: location::Region) {
+        self.current_chunk().write_byte(byte, location);
+    }
+
+    fn emit_return(&mut self) {
+        let location = self.previous.location;
+        if self.state.function_type == FunctionType::Initializer {
+            self.emit_op_code(chunk::OpCode::GetLocal, location);
+            self.emit_byte(0, location);
         } else {
-            message.push_str("   | ");
+            self.emit_op_code(chunk::OpCode::Nil, location);
+        }
+        self.emit_op_code(chunk::OpCode::Return, location);
+    }
+
+    fn emit_jump(&mut self, op_code: chunk::OpCode) -> usize {
+        let location = self.previous.location;
+        self.emit_op_code(op_code, location);
+        self.emit_byte(0xff, location);
+        self.emit_byte(0xff, location);
+        self.current_chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error("Too much code to jump over");
+            return;
+        };
+
+        let [high, low] = jump.to_be_bytes();
+        self.current_chunk().code[offset] = high;
+        self.current_chunk().code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let location = self.previous.location;
+        self.emit_op_code(chunk::OpCode::Loop, location);
+
+        let offset = self.current_chunk().code.len() - loop_start + 2;
+        let [high, low] = match u16::try_from(offset) {
+            Ok(offset) => offset.to_be_bytes(),
+            Err(_) => {
+                self.error("Loop body too large");
+                [0, 0]
+            }
+        };
+        self.emit_byte(high, location);
+        self.emit_byte(low, location);
+    }
+
+    fn emit_constant(&mut self, value: value::Value) {
+        let location = self.previous.location;
+        if self.current_chunk().write_constant(value, location).is_none() {
+            self.error("Too many constants in one chunk");
+        }
+    }
+
+    /// Emits an op code taking a constant index, in its long form if the index needs it.
+    fn emit_constant_op(&mut self, op_code: chunk::OpCode, constant: usize, location: location::Region) {
+        if !self.current_chunk().write_constant_op(op_code, constant, location) {
+            self.error("Too many constants in one chunk");
+        }
+    }
+
+    fn make_constant(&mut self, value: value::Value) -> usize {
+        match self.current_chunk().try_add_constant(value) {
+            Some(constant) => constant,
+            None => {
+                self.error("Too many constants in one chunk");
+                0
+            }
+        }
+    }
+
+    fn end_compiler(&mut self) -> FunctionState<'a> {
+        self.emit_return();
+        self.current_chunk().finish_constants();
+
+        #[cfg(feature = "debug_chunk")]
+        if !self.had_error {
+            let function = &self.state.function;
+            let name = function.name.map_or("<script>".to_string(), |name| name.to_string());
+            disassemble_chunk(self.reporter, &function.chunk, &name);
+        }
+
+        let enclosing = self
+            .enclosing
+            .pop()
+            .unwrap_or_else(|| FunctionState::new(FunctionType::Script, None));
+        std::mem::replace(&mut self.state, enclosing)
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        let token = self.current.clone();
+        self.error_at(&token, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        let token = self.previous.clone();
+        self.error_at(&token, message);
+    }
+
+    fn error_at(&mut self, token: &Token, message: &str) {
+        if self.panic_mode {
+            return;
         }
+        self.panic_mode = true;
+        self.had_error = true;
 
-        message.push_str(&format!("{:?}", token.token_type));
-        reporter.add_message(&message);
+        let message = match token.token_type {
+            TokenType::Eof => format!("Error at end: {message}"),
+            TokenType::Error => format!("Error: {message}"),
+            _ => format!("Error at '{}': {message}", token.lexeme),
+        };
+        self.reporter.add_diagnostic(&token.location, &message);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
 
-        if token.token_type == TokenType::Eof {
-            break;
+    struct TestReporter {
+        diagnostics: RefCell<Vec<String>>,
+        messages: RefCell<Vec<String>>,
+    }
+
+    impl TestReporter {
+        fn new() -> Self {
+            Self {
+                diagnostics: RefCell::new(vec![]),
+                messages: RefCell::new(vec![]),
+            }
         }
     }
+
+    impl Reporter for TestReporter {
+        fn add_diagnostic(&self, location: &location::Region, message: &str) {
+            self.diagnostics
+                .borrow_mut()
+                .push(format!("[{location}]: {message}"));
+        }
+
+        fn add_message(&self, message: &str) {
+            self.messages.borrow_mut().push(message.to_string());
+        }
+
+        fn has_diagnostics(&self) -> bool {
+            !self.diagnostics.borrow().is_empty()
+        }
+    }
+
+    #[test]
+    fn byte_code() {
+        use chunk::OpCode::*;
+        let tests = vec![
+            ("", vec![Nil as u8, Return as u8]),
+            ("1;", vec![Constant as u8, 0, Pop as u8, Nil as u8, Return as u8]),
+            ("-1;", vec![Constant as u8, 0, Negate as u8, Pop as u8, Nil as u8, Return as u8]),
+            (
+                "1 + 2 * 3;",
+                vec![
+                    Constant as u8, 0, Constant as u8, 1, Constant as u8, 2,
+                    Multiply as u8, Add as u8, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            (
+                "(1 + 2) * 3;",
+                vec![
+                    Constant as u8, 0, Constant as u8, 1, Add as u8,
+                    Constant as u8, 2, Multiply as u8, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            ("print !nil;", vec![Nil as u8, Not as u8, Print as u8, Nil as u8, Return as u8]),
+            (
+                "print 1 <= 2;",
+                vec![Constant as u8, 0, Constant as u8, 1, Greater as u8, Not as u8, Print as u8, Nil as u8, Return as u8],
+            ),
+            (
+                "true != false == true;",
+                vec![True as u8, False as u8, Equal as u8, Not as u8, True as u8, Equal as u8, Pop as u8, Nil as u8, Return as u8],
+            ),
+            (
+                "1 - 2 - 3;",
+                vec![
+                    Constant as u8, 0, Constant as u8, 1, Subtract as u8,
+                    Constant as u8, 2, Subtract as u8, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            ("var a;", vec![Nil as u8, DefineGlobal as u8, 0, Nil as u8, Return as u8]),
+            (
+                "{ var a = 1; a = a; }",
+                vec![
+                    Constant as u8, 0, GetLocal as u8, 1, SetLocal as u8, 1, Pop as u8,
+                    Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            (
+                "{ var a; { var b = a; } }",
+                vec![Nil as u8, GetLocal as u8, 1, Pop as u8, Pop as u8, Nil as u8, Return as u8],
+            ),
+            (
+                "var a = 1; a = a;",
+                vec![
+                    Constant as u8, 1, DefineGlobal as u8, 0,
+                    GetGlobal as u8, 0, SetGlobal as u8, 0, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            (
+                "class A {}",
+                vec![Class as u8, 0, DefineGlobal as u8, 0, GetGlobal as u8, 0, Pop as u8, Nil as u8, Return as u8],
+            ),
+            (
+                "a.b = a.c;",
+                vec![
+                    GetGlobal as u8, 0, GetGlobal as u8, 0, GetProperty as u8, 2,
+                    SetProperty as u8, 1, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            (
+                "1; 2; 1;",
+                vec![
+                    Constant as u8, 0, Pop as u8, Constant as u8, 1, Pop as u8,
+                    Constant as u8, 0, Pop as u8, Nil as u8, Return as u8,
+                ],
+            ),
+            (
+                "a.b(1);",
+                vec![GetGlobal as u8, 0, Constant as u8, 2, Invoke as u8, 1, 1, Pop as u8, Nil as u8, Return as u8],
+            ),
+        ];
+
+        for (src, expected) in tests {
+            let reporter = TestReporter::new();
+            let mut heap = heap::Heap::new();
+            let function = compile(&reporter, &mut heap, &[], src).expect("compile failed");
+            let chunk = &function.as_function().expect("not a function").chunk;
+            assert_eq!(chunk.code, expected, "Unexpected byte code for source '{src}'");
+            assert_eq!(chunk.code.len(), chunk.locations.len());
+        }
+    }
+
+    #[test]
+    #[cfg_attr(feature = "debug_chunk", ignore)]
+    fn long_names() {
+        use chunk::OpCode::*;
+        let terms: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+        let src = format!("print {};\nvar a = 1; a = a.b;", terms.join(" + "));
+        let reporter = TestReporter::new();
+        let mut heap = heap::Heap::new();
+        let function = compile(&reporter, &mut heap, &[], &src).expect("compile failed");
+        let chunk = &function.as_function().expect("not a function").chunk;
+        let start = chunk.code.len() - 21;
+        assert_eq!(
+            chunk.code[start..],
+            [
+                Constant as u8, 1, DefineGlobalLong as u8, 0, 1, 44, GetGlobalLong as u8, 0, 1, 44,
+                GetPropertyLong as u8, 0, 1, 45, SetGlobalLong as u8, 0, 1, 44, Pop as u8, Nil as u8, Return as u8,
+            ]
+        );
+
+        let mut offset = start;
+        while offset < chunk.code.len() {
+            offset = crate::debug::disassemble_instruction(&reporter, chunk, offset);
+        }
+        assert_eq!(
+            *reporter.messages.borrow(),
+            vec![
+                format!("{start:04}    1 OP_CONSTANT         1 1.00"),
+                format!("{:04}    | OP_DEFINE_GLOBAL_LONG  300 a", start + 2),
+                format!("{:04}    | OP_GET_GLOBAL_LONG  300 a", start + 6),
+                format!("{:04}    | OP_GET_PROPERTY_LONG  301 b", start + 10),
+                format!("{:04}    | OP_SET_GLOBAL_LONG  300 a", start + 14),
+                format!("{:04}    | OP_POP", start + 18),
+                format!("{:04}    | OP_NIL", start + 19),
+                format!("{:04}    | OP_RETURN", start + 20),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(feature = "debug_chunk", ignore)]
+    fn closures() {
+        let src = "fun outer() {\n  var x = 1;\n  var y = 2;\n  fun middle() {\n    fun inner() { return y + x; }\n    return inner;\n  }\n  return middle;\n}";
+        let reporter = TestReporter::new();
+        let mut heap = heap::Heap::new();
+        let script = compile(&reporter, &mut heap, &[], src).expect("compile failed");
+        let functions = |function: object::ObjRef| -> Vec<object::ObjRef> {
+            let chunk = &function.as_function().expect("not a function").chunk;
+            (0..)
+                .map_while(|index| chunk.constants.get(index).copied())
+                .filter_map(|constant| match constant {
+                    value::Value::Obj(obj) if obj.as_function().is_some() => Some(obj),
+                    _ => None,
+                })
+                .collect()
+        };
+        let outer = functions(script)[0];
+        let middle = functions(outer)[0];
+        let inner = functions(middle)[0];
+        assert_eq!(middle.as_function().map(|function| function.upvalue_count), Some(2));
+        assert_eq!(inner.as_function().map(|function| function.upvalue_count), Some(2));
+
+        for function in [outer, middle] {
+            let function = function.as_function().expect("not a function");
+            crate::debug::disassemble_chunk(&reporter, &function.chunk, &function.name.unwrap().to_string());
+        }
+        assert_eq!(
+            *reporter.messages.borrow(),
+            vec![
+                "=== outer ===",
+                "0000    1 OP_CONSTANT         0 1.00",
+                "0002    2 OP_CONSTANT         1 2.00",
+                "0004    6 OP_CLOSURE          2 <fn middle>",
+                "0006    |                     local 2",
+                "0008    |                     local 1",
+                "0010    7 OP_GET_LOCAL        3",
+                "0012    | OP_RETURN",
+                "0013    8 OP_NIL",
+                "0014    | OP_RETURN",
+                "=== middle ===",
+                "0000    4 OP_CLOSURE          0 <fn inner>",
+                "0002    |                     upvalue 0",
+                "0004    |                     upvalue 1",
+                "0006    5 OP_GET_LOCAL        1",
+                "0008    | OP_RETURN",
+                "0009    6 OP_NIL",
+                "0010    | OP_RETURN",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let tests = vec![
+            ("1 +", "[0:2-3]: Error at end: Expect expression"),
+            ("(1", "[0:1-2]: Error at end: Expect ')' after expression"),
+            ("1 2", "[0:2-3]: Error at '2': Expect ';' after expression"),
+            ("print 1", "[0:6-7]: Error at end: Expect ';' after value"),
+            ("1 $", "[0:2-3]: Error: Unexpected character"),
+            ("var 1 = 2;", "[0:4-5]: Error at '1': Expect variable name"),
+            ("var a = 1", "[0:8-9]: Error at end: Expect ';' after variable declaration"),
+            ("1 + 2 = 3;", "[0:6-7]: Error at '=': Invalid assignment target"),
+            ("{ var a; var a; }", "[0:13-14]: Error at 'a': Already a variable with this name in this scope"),
+            ("{ var a = 1;\n  { var a = a; } }", "[1:12-13]: Error at 'a': Can't read local variable in its own initializer"),
+            ("{ print 1;", "[0:9-10]: Error at end: Expect '}' after block"),
+            ("return 1;", "[0:0-6]: Error at 'return': Can't return from top-level code"),
+            ("fun 1() {}", "[0:4-5]: Error at '1': Expect function name"),
+            ("fun f {}", "[0:6-7]: Error at '{': Expect '(' after function name"),
+            ("fun f(a b) {}", "[0:8-9]: Error at 'b': Expect ')' after parameters"),
+            ("fun f() print 1;", "[0:8-13]: Error at 'print': Expect '{' before function body"),
+            ("f(1, 2;", "[0:6-7]: Error at ';': Expect ')' after arguments"),
+            ("class 1 {}", "[0:6-7]: Error at '1': Expect class name"),
+            ("class A", "[0:6-7]: Error at end: Expect '{' before class body"),
+            ("class A { 1 }", "[0:10-11]: Error at '1': Expect method name"),
+            ("class A { f() {}", "[0:15-16]: Error at end: Expect '}' after class body"),
+            ("a.1;", "[0:2-3]: Error at '1': Expect property name after '.'"),
+            ("print this;", "[0:6-10]: Error at 'this': Can't use 'this' outside of a class"),
+            ("fun f() { this; }", "[0:10-14]: Error at 'this': Can't use 'this' outside of a class"),
+            ("class A { init() { return 1; } }", "[0:19-25]: Error at 'return': Can't return a value from an initializer"),
+            ("class A < A {}", "[0:10-11]: Error at 'A': A class can't inherit from itself"),
+            ("class A < 1 {}", "[0:10-11]: Error at '1': Expect superclass name"),
+            ("super.f();", "[0:0-5]: Error at 'super': Can't use 'super' outside of a class"),
+            ("fun f() { super.f(); }", "[0:10-15]: Error at 'super': Can't use 'super' outside of a class"),
+            ("class A { f() { super.f(); } }", "[0:16-21]: Error at 'super': Can't use 'super' in a class with no superclass"),
+            ("class A {} class B < A { f() { super; } }", "[0:36-37]: Error at ';': Expect '.' after 'super'"),
+            ("class A {} class B < A { f() { super.1; } }", "[0:37-38]: Error at '1': Expect superclass method name"),
+            ("print 1; /* a /* b */", "[0:9-21]: Error: Unterminated block comment"),
+            ("print 0x;", "[0:6-8]: Error: Expect hexadecimal digits after '0x'"),
+            ("print 1e+;", "[0:6-9]: Error: Expect digits in exponent"),
+            ("print 1__000;", "[0:6-12]: Error: Invalid digit separator"),
+            ("print 0b102;", "[0:6-11]: Error: Invalid digit in binary literal"),
+            ("print 0x1G;", "[0:6-10]: Error: Invalid digit in hexadecimal literal"),
+            ("print \"a\\qb\";", "[0:8-10]: Error: Invalid escape sequence"),
+            ("print \"\\u{zz}\";", "[0:7-11]: Error: Invalid unicode escape"),
+        ];
+
+        for (src, expected) in tests {
+            let reporter = TestReporter::new();
+            let mut heap = heap::Heap::new();
+            assert!(compile(&reporter, &mut heap, &[], src).is_none(), "Expected error for '{src}'");
+            assert_eq!(
+                reporter.diagnostics.borrow().first().map(String::as_str),
+                Some(expected),
+                "Unexpected diagnostic for source '{src}'"
+            );
+        }
+    }
+
+    #[test]
+    fn too_many_locals() {
+        let reporter = TestReporter::new();
+        let mut heap = heap::Heap::new();
+        let mut src = "{".to_string();
+        (0..LOCALS_MAX).for_each(|i| src.push_str(&format!(" var a{i};")));
+        src.push('}');
+
+        assert!(compile(&reporter, &mut heap, &[], &src).is_none());
+        assert_eq!(
+            *reporter.diagnostics.borrow(),
+            vec!["[0:2446-2450]: Error at 'a255': Too many local variables in function"]
+        );
+    }
+
+    #[test]
+    fn too_many_parameters() {
+        let parameters: Vec<String> = (0..=PARAMETERS_MAX).map(|i| format!("a{i}")).collect();
+        let arguments = vec!["nil"; PARAMETERS_MAX + 1];
+        let tests = vec![
+            (
+                format!("fun f({}) {{}}", parameters.join(", ")),
+                "[0:1426-1430]: Error at 'a255': Can't have more than 255 parameters",
+            ),
+            (
+                format!("f({});", arguments.join(", ")),
+                "[0:1277-1280]: Error at 'nil': Can't have more than 255 arguments",
+            ),
+        ];
+
+        for (src, expected) in tests {
+            let reporter = TestReporter::new();
+            let mut heap = heap::Heap::new();
+            assert!(compile(&reporter, &mut heap, &[], &src).is_none());
+            assert_eq!(*reporter.diagnostics.borrow(), vec![expected]);
+        }
+    }
+
+    #[test]
+    fn jump_limits() {
+        let tests = vec![
+            (
+                format!("if (true) {{\n{}}}", "nil;\n".repeat(33_000)),
+                "[33001:0-1]: Error at '}': Too much code to jump over",
+            ),
+            (
+                format!("while (true) {{\n{}}}", "nil;\n".repeat(33_000)),
+                "[33001:0-1]: Error at '}': Loop body too large",
+            ),
+        ];
+
+        for (src, expected) in tests {
+            let reporter = TestReporter::new();
+            let mut heap = heap::Heap::new();
+            assert!(compile(&reporter, &mut heap, &[], &src).is_none());
+            assert_eq!(*reporter.diagnostics.borrow(), vec![expected]);
+        }
+    }
+
+    #[test]
+    fn synchronize() {
+        let reporter = TestReporter::new();
+        let mut heap = heap::Heap::new();
+        assert!(compile(&reporter, &mut heap, &[], "1 +; var = 2; print 3;\n print ;").is_none());
+        assert_eq!(
+            *reporter.diagnostics.borrow(),
+            vec![
+                "[0:3-4]: Error at ';': Expect expression",
+                "[0:9-10]: Error at '=': Expect variable name",
+                "[1:7-8]: Error at ';': Expect expression",
+            ]
+        );
+    }
 }
@@ -1,5 +1,31 @@
+use crate::chunk;
+use std::fmt;
+
+#[derive(PartialEq, Debug)]
 pub enum InterpretResult {
     Ok,
     CompileError,
     RuntimeError,
+    InvalidChunk(chunk::VerifyError),
 }
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
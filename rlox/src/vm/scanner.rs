@@ -1,6 +1,7 @@
 use std::{iter::Peekable, str::CharIndices};
 
 use crate::{
+    location::Location,
     vm::token::{Token, TokenType},
     Region,
 };
@@ -58,12 +59,13 @@ impl CharacterCache {
 }
 
 pub struct Scanner<'a> {
-    token_start_line_number: u16,
-    token_start_line_offset: u16,
-    current_line_number: u16,
-    current_line_offset: i16,
+    token_start: Location,
+    // Location of the last character consumed and of the one after it
+    current_location: Location,
+    next_location: Location,
     start_of_token: usize,
     current: usize,
+    current_end: usize,
     source: &'a str,
     char_indices: Peekable<CharIndices<'a>>,
     character_cache: CharacterCache
@@ -72,20 +74,22 @@ pub struct Scanner<'a> {
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
         Scanner {
-            token_start_line_number: 0,
-            token_start_line_offset: 0,
-            current_line_number: 0,
-            current_line_offset: -1,
+            token_start: Location::new(0, 0),
+            current_location: Location::new(0, 0),
+            next_location: Location::new(0, 0),
             start_of_token: 0,
             current: 0,
+            current_end: 0,
             source,
             char_indices: source.char_indices().peekable(),
             character_cache: CharacterCache::new()
         }
     }
 
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_white_space();
+    pub fn scan_token(&mut self) -> Token<'a> {
+        if let Some(error) = self.skip_white_space() {
+            return error;
+        }
 
         if self.is_at_end() {
             self.start_of_token();
@@ -94,11 +98,11 @@ impl<'a> Scanner<'a> {
 
         let c = self.advance(false).unwrap();
         self.start_of_token();
-        if is_alpha(Some(c)) {
+        if is_identifier_start(Some(c)) {
             return self.make_identifier_token()
         }
         if is_digit(Some(c)) {
-            return self.make_number_token()
+            return self.make_number_token(c)
         }
         match c {
             '(' => return self.make_token(TokenType::LeftParen),
@@ -110,6 +114,7 @@ impl<'a> Scanner<'a> {
             '.' => return self.make_token(TokenType::Dot),
             '-' => return self.make_token(TokenType::Minus),
             '+' => return self.make_token(TokenType::Plus),
+            '/' if self.remaining().starts_with("//") => return self.make_doc_comment_token(),
             '/' => return self.make_token(TokenType::Slash),
             '*' => return self.make_token(TokenType::Star),
             '!' => return two_char_token!(self, '=', TokenType::BangEqual, TokenType::Bang),
@@ -120,88 +125,186 @@ impl<'a> Scanner<'a> {
             _ => (),
         }
 
-        return self.make_error_token("Unexpected character");
+        self.make_error_token("Unexpected character")
     }
 
     fn start_of_token(&mut self) {
         self.start_of_token = self.current;
-        self.token_start_line_number = self.current_line_number;
-        self.token_start_line_offset = u16::try_from(self.current_line_offset).unwrap_or(0);
+        self.token_start = self.current_location;
     }
 
+    // Advancing past a newline has already moved `next_location` to the following line, this moves
+    // the current location there too so a token started before any character starts at column 0
     fn new_line(&mut self) {
-        self.current_line_number += 1;
-        self.current_line_offset = -1;
+        self.current_location = self.next_location;
+    }
+
+    fn token_region(&self) -> Region {
+        Region {
+            start: self.token_start,
+            end: self.next_location,
+        }
     }
 
-    fn make_token(&self, token_type: TokenType) -> Token {
+    fn make_token(&self, token_type: TokenType) -> Token<'a> {
         let include_lexeme = token_type != TokenType::Eof ;
         Token::new(
             token_type,
-            if include_lexeme {&self.source[self.start_of_token..(self.current+1)]} else {""},
-            Region::new(
-                self.token_start_line_number,
-                self.token_start_line_offset,
-                self.current_line_number,
-                u16::try_from(self.current_line_offset).unwrap_or(0)+1
-            ),
+            if include_lexeme {&self.source[self.start_of_token..self.current_end]} else {""},
+            self.token_region(),
         )
     }
 
-    fn make_error_token(&self, message: &'static str) -> Token {
-        Token::new(
-            TokenType::Error,
-            message,
-            Region::new(
-                self.token_start_line_number,
-                self.token_start_line_offset,
-                self.current_line_number,
-                u16::try_from(self.current_line_offset).unwrap_or(0)+1
-            ),
-        )
+    fn make_error_token(&self, message: &'static str) -> Token<'a> {
+        Token::new(TokenType::Error, message, self.token_region())
     }
 
-    fn make_string_token(&mut self) -> Token {
+    fn make_string_token(&mut self) -> Token<'a> {
         while !self.is_at_end() {
             let next_char = self.peek_next_char(false);
             match next_char {
                 Some('"') => break,
                 Some('\n') => {self.advance(false); self.new_line();},
+                Some('\\') => {
+                    // Skip the escaped character so that \" does not end the string
+                    self.advance(false);
+                    if let Some('\n') = self.advance(false) {
+                        self.new_line();
+                    }
+                },
                 _ => {self.advance(false);},
             }
         }
         if self.is_at_end() {
-            self.make_error_token("Unterminated string")
-        } else {
-            self.advance(false);
-            self.make_token(TokenType::String)
+            return self.make_error_token("Unterminated string");
+        }
+        self.advance(false);
+        let token = self.make_token(TokenType::String);
+        match unescape(&token.lexeme[1..token.lexeme.len()-1]) {
+            Ok(_) => token,
+            Err(error) => {
+                // Offsets are relative to the body, which starts after the opening quote
+                let region = Region {
+                    start: self.location_in_token(token.lexeme, error.start + 1),
+                    end: self.location_in_token(token.lexeme, error.end + 1),
+                };
+                Token::new(TokenType::Error, error.message, region)
+            }
         }
     }
 
-    fn make_number_token(&mut self) -> Token {
-        while is_digit(self.peek_next_char(false)) {
-            self.advance(false);
+    fn location_in_token(&self, lexeme: &str, byte_offset: usize) -> Location {
+        lexeme[..byte_offset].chars().fold(self.token_start, Location::advanced_past)
+    }
+
+    fn make_doc_comment_token(&mut self) -> Token<'a> {
+        self.consume_line();
+        self.make_token(TokenType::DocComment)
+    }
+
+    fn make_number_token(&mut self, first: char) -> Token<'a> {
+        if first == '0' {
+            match self.peek_next_char(false) {
+                Some('x') | Some('X') => {
+                    return self.make_radix_number_token(
+                        |c| c.is_ascii_hexdigit(),
+                        "Expect hexadecimal digits after '0x'",
+                        "Invalid digit in hexadecimal literal",
+                    )
+                }
+                Some('b') | Some('B') => {
+                    return self.make_radix_number_token(
+                        |c| c == '0' || c == '1',
+                        "Expect binary digits after '0b'",
+                        "Invalid digit in binary literal",
+                    )
+                }
+                _ => (),
+            }
+        }
+
+        if !self.consume_digits(|c| c.is_ascii_digit()) {
+            return self.make_error_token("Invalid digit separator");
         }
         if let Some('.') = self.peek_next_char(false) {
             if is_digit(self.peek_next_next_char()) {
                 self.advance(false);
-
-                while is_digit(self.peek_next_char(false)) {
-                    self.advance(false);
+                if !self.consume_digits(|c| c.is_ascii_digit()) {
+                    return self.make_error_token("Invalid digit separator");
                 }
             }
         }
+        // An `e` followed by more of an identifier, as in `4else`, is not an exponent
+        if let Some('e') | Some('E') = self.peek_next_char(false) {
+            let after = self.peek_next_next_char();
+            if is_identifier_continue(after) && !is_digit(after) {
+                return self.make_token(TokenType::Number);
+            }
+            self.advance(false);
+            if let Some('+') | Some('-') = self.peek_next_char(false) {
+                self.advance(false);
+            }
+            if !is_digit(self.peek_next_char(false)) {
+                return self.make_error_token("Expect digits in exponent");
+            }
+            if !self.consume_digits(|c| c.is_ascii_digit()) {
+                return self.make_error_token("Invalid digit separator");
+            }
+        }
 
         self.make_token(TokenType::Number)
     }
 
-    fn make_identifier_token(&mut self) -> Token {
-        while is_alpha(self.peek_next_char(false)) || is_digit(self.peek_next_char(false)){
+    fn make_radix_number_token(
+        &mut self,
+        is_radix_digit: fn(char) -> bool,
+        missing_message: &'static str,
+        invalid_message: &'static str,
+    ) -> Token<'a> {
+        // The 'x' or 'b' of the prefix
+        self.advance(false);
+        if !self.peek_next_char(false).is_some_and(is_radix_digit) {
+            return self.make_error_token(missing_message);
+        }
+        if !self.consume_digits(is_radix_digit) {
+            return self.make_error_token("Invalid digit separator");
+        }
+        // Rather than splitting `0b102` into two numbers, the whole literal is rejected
+        if is_identifier_continue(self.peek_next_char(false)) {
+            while is_identifier_continue(self.peek_next_char(false)) {
+                self.advance(false);
+            }
+            return self.make_error_token(invalid_message);
+        }
+
+        self.make_token(TokenType::Number)
+    }
+
+    /// Consumes digits along with single `_` separators between them. Returns `false` if a
+    /// separator is not followed by a digit, having consumed the rest of the run of digits and `_`.
+    fn consume_digits(&mut self, is_digit: fn(char) -> bool) -> bool {
+        while let Some(c) = self.peek_next_char(false) {
+            if c == '_' && !self.peek_next_next_char().is_some_and(is_digit) {
+                while self.peek_next_char(false).is_some_and(|c| c == '_' || is_digit(c)) {
+                    self.advance(false);
+                }
+                return false;
+            }
+            if c != '_' && !is_digit(c) {
+                break;
+            }
+            self.advance(false);
+        }
+        true
+    }
+
+    fn make_identifier_token(&mut self) -> Token<'a> {
+        while is_identifier_continue(self.peek_next_char(false)) {
             self.advance(false);
         }
 
         self.make_token(identifier_type(
-            &self.source[self.start_of_token..(self.current+1)]
+            &self.source[self.start_of_token..self.current_end]
         ))
     }
 
@@ -212,8 +315,10 @@ impl<'a> Scanner<'a> {
             self.char_indices.next()
         };
         if let Some((i, c)) = next_char {
-            self.current_line_offset += 1;
+            self.current_location = self.next_location;
+            self.next_location = self.next_location.advanced_past(c);
             self.current = i;
+            self.current_end = i + c.len_utf8();
             Some(c)
         } else {
             None
@@ -242,6 +347,15 @@ impl<'a> Scanner<'a> {
         }
     }
 
+    /// The source from the next unconsumed character onwards
+    fn remaining(&mut self) -> &'a str {
+        let next = match self.character_cache.as_ref() {
+            Some((i, _)) => *i,
+            None => self.char_indices.peek().map_or(self.source.len(), |(i, _)| *i),
+        };
+        &self.source[next..]
+    }
+
     fn peek_next_next_char(&mut self) -> Option<char> {
         self.character_cache.store(self.char_indices.next());
         self.peek_next_char(true)
@@ -255,7 +369,7 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn skip_white_space(&mut self) {
+    fn skip_white_space(&mut self) -> Option<Token<'a>> {
         loop {
             match self.peek_next_char(false) {
                 Some(' ') | Some('\r') | Some('\t') => {
@@ -265,20 +379,53 @@ impl<'a> Scanner<'a> {
                     self.advance(false);
                     self.new_line();
                 }
-                //** Need to peek the next character **
                 Some('/') => {
-                    if let Some('/') = self.peek_next_next_char() {
-                        self.consume_line()
-                    }
-                    if self.character_cache.is_some() {
-                        return;
+                    let rest = self.remaining();
+                    // `///` starts a doc comment which is scanned as a token, `////` is a plain comment
+                    if rest.starts_with("///") && !rest.starts_with("////") {
+                        return None;
+                    } else if rest.starts_with("//") {
+                        self.consume_line();
+                    } else if rest.starts_with("/*") {
+                        if !self.consume_block_comment() {
+                            return Some(self.make_error_token("Unterminated block comment"));
+                        }
+                    } else {
+                        return None;
                     }
                 }
-                _ => return,
+                _ => return None,
             }
         }
     }
 
+    /// Consumes a possibly nested block comment, returning false if the input ends inside it
+    fn consume_block_comment(&mut self) -> bool {
+        self.advance(false);
+        self.start_of_token();
+        self.advance(false);
+        let mut depth = 1;
+        while depth > 0 {
+            let rest = self.remaining();
+            if rest.starts_with("/*") {
+                self.advance(false);
+                self.advance(false);
+                depth += 1;
+            } else if rest.starts_with("*/") {
+                self.advance(false);
+                self.advance(false);
+                depth -= 1;
+            } else {
+                match self.advance(false) {
+                    Some('\n') => self.new_line(),
+                    Some(_) => (),
+                    None => return false,
+                }
+            }
+        }
+        true
+    }
+
     fn consume_line(&mut self) {
         loop {
             match self.peek_next_char(false) {
@@ -291,15 +438,109 @@ impl<'a> Scanner<'a> {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct EscapeError {
+    pub start: usize,
+    pub end: usize,
+    pub message: &'static str,
+}
+
+impl EscapeError {
+    fn new(start: usize, end: usize, message: &'static str) -> Self {
+        Self { start, end, message }
+    }
+}
+
+/// Decodes the escape sequences in the body of a string literal. On failure the error holds the
+/// byte range of the offending escape within `body`.
+pub fn unescape(body: &str) -> Result<String, EscapeError> {
+    let mut result = String::with_capacity(body.len());
+    let mut chars = body.char_indices();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '\\')) => '\\',
+            Some((_, '"')) => '"',
+            Some((_, 'u')) => unicode_escape(start, &mut chars)?,
+            Some((i, c)) => return Err(EscapeError::new(start, i + c.len_utf8(), "Invalid escape sequence")),
+            None => return Err(EscapeError::new(start, body.len(), "Invalid escape sequence")),
+        };
+        result.push(escaped);
+    }
+    Ok(result)
+}
+
+fn unicode_escape(start: usize, chars: &mut CharIndices) -> Result<char, EscapeError> {
+    // Points one past the last consumed character, starting after the "\u"
+    let mut end = start + 2;
+    let mut value: u32 = 0;
+    let mut digits = 0;
+    let mut opened = false;
+    loop {
+        let Some((i, c)) = chars.next() else {
+            return Err(EscapeError::new(start, end, "Invalid unicode escape"));
+        };
+        end = i + c.len_utf8();
+        match c {
+            '{' if !opened => opened = true,
+            '}' if opened && digits > 0 => break,
+            _ if opened && digits < 6 && c.is_ascii_hexdigit() => {
+                value = value * 16 + c.to_digit(16).unwrap();
+                digits += 1;
+            }
+            _ => return Err(EscapeError::new(start, end, "Invalid unicode escape")),
+        }
+    }
+    char::from_u32(value).ok_or(EscapeError::new(start, end, "Invalid unicode code point"))
+}
+
+/// Converts the lexeme of a number token to its value, or None if it is malformed. Values too
+/// large for an f64 become infinity, whatever the radix.
+pub fn parse_number(lexeme: &str) -> Option<f64> {
+    let digits = lexeme.replace('_', "");
+    let radix: u32 = match digits.get(..2) {
+        Some("0x") | Some("0X") => 16,
+        Some("0b") | Some("0B") => 2,
+        _ => return digits.parse().ok(),
+    };
+    // The leading 64 bits or more are kept exactly and any set bits below them folded into the
+    // lowest one, which is enough for the cast to round to nearest like decimal literals do.
+    let bits_per_digit = radix.trailing_zeros();
+    let (mut mantissa, mut exponent, mut sticky) = (0u128, 0i32, false);
+    for c in digits[2..].chars() {
+        let digit = c.to_digit(radix)?;
+        if mantissa >> 64 == 0 {
+            mantissa = (mantissa << bits_per_digit) | digit as u128;
+        } else {
+            exponent = exponent.saturating_add(bits_per_digit as i32);
+            sticky |= digit != 0;
+        }
+    }
+    Some((mantissa | sticky as u128) as f64 * 2f64.powi(exponent))
+}
+
 fn is_digit(c: Option<char>) -> bool {
-    c.map_or(false, |v| v.is_ascii_digit() )
+    c.is_some_and(|v| v.is_ascii_digit())
 }
 
-fn is_alpha(c: Option<char>) -> bool {
-    c.map_or(false, |v| v.is_ascii_lowercase() || v.is_ascii_uppercase() || v == '_')
+fn is_identifier_start(c: Option<char>) -> bool {
+    c.is_some_and(|v| v == '_' || unicode_ident::is_xid_start(v))
+}
+
+fn is_identifier_continue(c: Option<char>) -> bool {
+    c.is_some_and(unicode_ident::is_xid_continue)
 }
 
 fn identifier_type(lexeme: &str) -> TokenType {
+    if !lexeme.is_ascii() {
+        return TokenType::Identifier;
+    }
     match &lexeme[0..1] {
         "a" => check_keyword(lexeme, 1, "nd", TokenType::And),
         "c" => check_keyword(lexeme, 1, "lass", TokenType::Class),
@@ -348,7 +589,7 @@ mod test {
     #[test]
     fn tokens() {
         let tests = vec![
-            ("", make_token(TokenType::Eof, "", 0, 0, 0, 1)),
+            ("", make_token(TokenType::Eof, "", 0, 0, 0, 0)),
             ("//comment
              !
              ", make_token(TokenType::Bang, "!", 1, 13, 1, 14)),
@@ -365,6 +606,21 @@ mod test {
             ("\"hello
             \"", make_token(TokenType::String, "\"hello\n            \"", 0, 0, 1, 13)),
             ("\"hello", make_token(TokenType::Error, "Unterminated string", 0, 0, 0, 6)),
+            ("\"a\\\"b\"", make_token(TokenType::String, "\"a\\\"b\"", 0, 0, 0, 6)),
+            ("\"a\\\"", make_token(TokenType::Error, "Unterminated string", 0, 0, 0, 4)),
+            ("\"ab\\qc\"", make_token(TokenType::Error, "Invalid escape sequence", 0, 3, 0, 5)),
+            ("\"a\n  \\u{12x}\"", make_token(TokenType::Error, "Invalid unicode escape", 1, 2, 1, 8)),
+            ("\"\\u{D800}\"", make_token(TokenType::Error, "Invalid unicode code point", 0, 1, 0, 9)),
+            ("/* comment */ !", make_token(TokenType::Bang, "!", 0, 14, 0, 15)),
+            ("/* outer /* inner */ still outer */ !", make_token(TokenType::Bang, "!", 0, 36, 0, 37)),
+            ("/* line\n   */ !", make_token(TokenType::Bang, "!", 1, 6, 1, 7)),
+            ("/**/!", make_token(TokenType::Bang, "!", 0, 4, 0, 5)),
+            (" /* outer /* inner */", make_token(TokenType::Error, "Unterminated block comment", 0, 1, 0, 21)),
+            ("/* a\n b", make_token(TokenType::Error, "Unterminated block comment", 0, 0, 1, 2)),
+            ("/// docs\n!", make_token(TokenType::DocComment, "/// docs", 0, 0, 0, 8)),
+            ("  ///", make_token(TokenType::DocComment, "///", 0, 2, 0, 5)),
+            ("//// not docs\n!", make_token(TokenType::Bang, "!", 1, 0, 1, 1)),
+            ("/ *", make_token(TokenType::Slash, "/", 0, 0, 0, 1)),
             ("10", make_token(TokenType::Number, "10", 0, 0, 0, 2)),
             ("10.", make_token(TokenType::Number, "10", 0, 0, 0, 2)),
             ("10.1", make_token(TokenType::Number, "10.1", 0, 0, 0, 4)),
@@ -380,6 +636,123 @@ mod test {
             ("f", make_token(TokenType::Identifier, "f", 0, 0, 0, 1)),
             ("for", make_token(TokenType::For, "for", 0, 0, 0, 3)),
             ("fore", make_token(TokenType::Identifier, "fore", 0, 0, 0, 4)),
+            ("_a1", make_token(TokenType::Identifier, "_a1", 0, 0, 0, 3)),
+            ("0x1F", make_token(TokenType::Number, "0x1F", 0, 0, 0, 4)),
+            ("0XaB_cd;", make_token(TokenType::Number, "0XaB_cd", 0, 0, 0, 7)),
+            ("0x", make_token(TokenType::Error, "Expect hexadecimal digits after '0x'", 0, 0, 0, 2)),
+            ("0x_1", make_token(TokenType::Error, "Expect hexadecimal digits after '0x'", 0, 0, 0, 2)),
+            ("0b1010", make_token(TokenType::Number, "0b1010", 0, 0, 0, 6)),
+            ("0b102", make_token(TokenType::Error, "Invalid digit in binary literal", 0, 0, 0, 5)),
+            ("0b12_3", make_token(TokenType::Error, "Invalid digit in binary literal", 0, 0, 0, 6)),
+            ("0x1G", make_token(TokenType::Error, "Invalid digit in hexadecimal literal", 0, 0, 0, 4)),
+            ("0xFFtrue;", make_token(TokenType::Error, "Invalid digit in hexadecimal literal", 0, 0, 0, 8)),
+            ("0xF.5", make_token(TokenType::Number, "0xF", 0, 0, 0, 3)),
+            ("0b", make_token(TokenType::Error, "Expect binary digits after '0b'", 0, 0, 0, 2)),
+            ("1_000_000", make_token(TokenType::Number, "1_000_000", 0, 0, 0, 9)),
+            ("1_000.000_1", make_token(TokenType::Number, "1_000.000_1", 0, 0, 0, 11)),
+            ("1._5", make_token(TokenType::Number, "1", 0, 0, 0, 1)),
+            ("1_", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 2)),
+            ("1__2", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 4)),
+            ("1_.5", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 2)),
+            ("1.5_;", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 4)),
+            ("1e1_0_", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 6)),
+            ("0x1_", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 4)),
+            ("0b1__0 ", make_token(TokenType::Error, "Invalid digit separator", 0, 0, 0, 6)),
+            ("1.5e-3", make_token(TokenType::Number, "1.5e-3", 0, 0, 0, 6)),
+            ("2E+10", make_token(TokenType::Number, "2E+10", 0, 0, 0, 5)),
+            ("2e10.5", make_token(TokenType::Number, "2e10", 0, 0, 0, 4)),
+            ("1e", make_token(TokenType::Error, "Expect digits in exponent", 0, 0, 0, 2)),
+            ("1else", make_token(TokenType::Number, "1", 0, 0, 0, 1)),
+            ("1.5e-;", make_token(TokenType::Error, "Expect digits in exponent", 0, 0, 0, 5)),
+        ];
+
+        for (src, expected) in tests {
+            let mut scanner = Scanner::new(src);
+            let token = scanner.scan_token();
+            let expected = Token { location: locate(src, expected.location), ..expected };
+
+            assert_eq!(token, expected, "Unexpected token {expected:?} for source '{src}'")
+        }
+    }
+
+    #[test]
+    fn huge_inputs() {
+        let src = format!("{}!", "\n".repeat(70_000));
+        let token = Scanner::new(&src).scan_token();
+        assert_eq!(token.location, locate(&src, Region::new_single_line(70_000, 0, 1)));
+        assert_eq!(token.location.start.index, 70_000);
+
+        let src = format!("/*{}*/!", "é".repeat(70_000));
+        let token = Scanner::new(&src).scan_token();
+        assert_eq!(token.location, make_region((0, 70_004, 140_004, 70_004), (0, 70_005, 140_005, 70_005)));
+
+        let src = format!("var a = \"{}\";", "long string\n".repeat(70_000));
+        let mut scanner = Scanner::new(&src);
+        let tokens: Vec<_> = (0..4).map(|_| scanner.scan_token()).collect();
+        assert_eq!(tokens[3].token_type, TokenType::String);
+        assert_eq!(tokens[3].location, locate(&src, Region::new(0, 8, 70_000, 1)));
+        assert_eq!(tokens[3].location.end.index as usize, src.len() - 1);
+
+        let last = Location { index: u32::MAX - 1, ..Location::new(u32::MAX, u32::MAX) };
+        assert_eq!(last.advanced_past('é'), Location { index: u32::MAX, ..last });
+        assert_eq!(last.advanced_past('\n'), Location { index: u32::MAX, ..Location::new(u32::MAX, 0) });
+    }
+
+    #[test]
+    fn numbers() {
+        let tests = vec![
+            ("0", Some(0.0)),
+            ("1_000_000", Some(1_000_000.0)),
+            ("10.25", Some(10.25)),
+            ("1.5e-3", Some(1.5e-3)),
+            ("2E+10", Some(2e10)),
+            ("0x1F", Some(31.0)),
+            ("0XFF_FF", Some(65535.0)),
+            ("0b1010", Some(10.0)),
+            ("0x1FFFFFFFFFFFFF", Some(9007199254740991.0)),
+            ("0x20000000000000001", Some(36893488147419103232.0)),
+            ("0x1_0000_0000_0000_0000_0000_0000_0000_0000", Some(2f64.powi(128))),
+            // Halfway between two floats plus a bit beyond the first 64, so it rounds up
+            ("0x1000_0000_0000_0080_0000_0000_0000_0001", Some(2f64.powi(124) + 2f64.powi(72))),
+            ("0x1000_0000_0000_0080_0000_0000_0000_0000", Some(2f64.powi(124))),
+            ("0b111111111111111111111111111111111111111111111111111111111111111111", Some(2f64.powi(66))),
+        ];
+
+        for (lexeme, expected) in tests {
+            assert_eq!(parse_number(lexeme), expected, "Unexpected value for '{lexeme}'");
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let tests = vec![
+            ("plain", Ok("plain".to_string())),
+            ("a\\nb\\tc\\rd", Ok("a\nb\tc\rd".to_string())),
+            ("\\\\ \\\"", Ok("\\ \"".to_string())),
+            ("\\u{41}\\u{1F600}", Ok("A\u{1F600}".to_string())),
+            ("ab\\q", Err(EscapeError::new(2, 4, "Invalid escape sequence"))),
+            ("a\\", Err(EscapeError::new(1, 2, "Invalid escape sequence"))),
+            ("\\u41", Err(EscapeError::new(0, 3, "Invalid unicode escape"))),
+            ("\\u{}", Err(EscapeError::new(0, 4, "Invalid unicode escape"))),
+            ("\\u{1234567}", Err(EscapeError::new(0, 10, "Invalid unicode escape"))),
+            ("\\u{41", Err(EscapeError::new(0, 5, "Invalid unicode escape"))),
+            ("\\u{110000}", Err(EscapeError::new(0, 10, "Invalid unicode code point"))),
+        ];
+
+        for (body, expected) in tests {
+            assert_eq!(unescape(body), expected, "Unexpected result for '{body}'");
+        }
+    }
+
+    #[test]
+    fn unicode_locations() {
+        let tests = vec![
+            ("café", Token::new(TokenType::Identifier, "café", make_region((0, 0, 0, 0), (0, 4, 5, 4)))),
+            ("日本語 =", Token::new(TokenType::Identifier, "日本語", make_region((0, 0, 0, 0), (0, 3, 9, 3)))),
+            ("\"😀\" x", Token::new(TokenType::String, "\"😀\"", make_region((0, 0, 0, 0), (0, 3, 6, 4)))),
+            ("\"😀\\q\"", Token::new(TokenType::Error, "Invalid escape sequence", make_region((0, 2, 5, 3), (0, 4, 7, 5)))),
+            ("\u{00A0}", Token::new(TokenType::Error, "Unexpected character", make_region((0, 0, 0, 0), (0, 1, 2, 1)))),
+            ("\"日\"\n  éa", Token::new(TokenType::String, "\"日\"", make_region((0, 0, 0, 0), (0, 3, 5, 3)))),
         ];
 
         for (src, expected) in tests {
@@ -388,9 +761,35 @@ mod test {
 
             assert_eq!(token, expected, "Unexpected token {expected:?} for source '{src}'")
         }
+
+        let mut scanner = Scanner::new("\"😀\" é1 +");
+        scanner.scan_token();
+        let token = scanner.scan_token();
+        assert_eq!(token, Token::new(TokenType::Identifier, "é1", make_region((0, 4, 7, 5), (0, 6, 10, 7))));
+        let token = scanner.scan_token();
+        assert_eq!(token, Token::new(TokenType::Plus, "+", make_region((0, 7, 11, 8), (0, 8, 12, 9))));
+    }
+
+    // Regions on the first line, where the byte offset is also the index
+    fn make_region(start: (u32, u32, u32, u32), end: (u32, u32, u32, u32)) -> Region {
+        let location = |(line, offset, byte_offset, utf16_offset)| {
+            assert_eq!(line, 0);
+            Location { line, offset, byte_offset, utf16_offset, index: byte_offset }
+        };
+        Region { start: location(start), end: location(end) }
+    }
+
+    // Fills in the indices of a region given by lines and char offsets into `src`
+    fn locate(src: &str, region: Region) -> Region {
+        let index = |location: Location| {
+            let line_start: usize = src.split_inclusive('\n').take(location.line as usize).map(str::len).sum();
+            let column: usize = src[line_start..].chars().take(location.offset as usize).map(char::len_utf8).sum();
+            Location { index: (line_start + column) as u32, ..location }
+        };
+        Region { start: index(region.start), end: index(region.end) }
     }
 
-    fn make_token(token_type: TokenType, lexeme: &str, start_line: u16, start_char: u16, end_line: u16, end_char: u16) -> Token {
+    fn make_token(token_type: TokenType, lexeme: &str, start_line: u32, start_char: u32, end_line: u32, end_char: u32) -> Token<'_> {
         Token::new(token_type, lexeme, Region::new(start_line, start_char, end_line, end_char))
     }
 
@@ -1,12 +1,13 @@
 use crate::location;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum TokenType {
     And,
     Bang,
     BangEqual,
     Class,
     Comma,
+    DocComment,
     Dot,
     Else,
     Eof,
@@ -44,7 +45,7 @@ pub enum TokenType {
     While,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Token<'a> {
     pub token_type: TokenType,
     pub lexeme: &'a str,
@@ -4,6 +4,7 @@ use std::cell::RefCell;
 type TestCode = Box<dyn Fn(&mut rlox::Chunk)>;
 
 #[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
 fn tests() {
     let tests: Vec<(TestCode, Vec<&str>)> = vec![
         (
@@ -14,31 +15,17 @@ fn tests() {
         ),
         (
             Box::new(|chunk: &mut rlox::Chunk| {
-                let constant = chunk.add_constant(rlox::Value::Number(1.2));
-                chunk.write_op_code(
-                    rlox::OpCode::Constant,
-                    rlox::Region::new_single_line(1, 0, 0),
-                );
-                chunk.write_byte(constant as u8, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_constant(rlox::Value::Number(1.2), rlox::Region::new_single_line(1, 0, 0));
 
-                let constant = chunk.add_constant(rlox::Value::Number(3.4));
-                chunk.write_op_code(
-                    rlox::OpCode::Constant,
-                    rlox::Region::new_single_line(1, 0, 0),
-                );
-                chunk.write_byte(constant as u8, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_constant(rlox::Value::Number(3.4), rlox::Region::new_single_line(1, 0, 0));
 
                 chunk.write_op_code(rlox::OpCode::Add, rlox::Region::new_single_line(1, 0, 0));
 
-                let constant = chunk.add_constant(rlox::Value::Number(5.6));
-                chunk.write_op_code(
-                    rlox::OpCode::Constant,
-                    rlox::Region::new_single_line(2, 0, 0),
-                );
-                chunk.write_byte(constant as u8, rlox::Region::new_single_line(2, 0, 0));
+                chunk.write_constant(rlox::Value::Number(5.6), rlox::Region::new_single_line(2, 0, 0));
 
                 chunk.write_op_code(rlox::OpCode::Divide, rlox::Region::new_single_line(2, 0, 0));
                 chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::new_single_line(2, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Print, rlox::Region::new_single_line(2, 0, 0));
                 chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(3, 0, 0));
             }),
             vec![
@@ -48,8 +35,38 @@ fn tests() {
                 "0005    2 OP_CONSTANT         2 5.60",
                 "0007    | OP_DIVIDE",
                 "0008    | OP_NEGATE",
-                "0009    3 OP_RETURN",
-                "[-0.82]",
+                "0009    | OP_PRINT",
+                "0010    3 OP_RETURN",
+                "-0.82",
+                "",
+            ],
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                let location = rlox::Region::new_single_line(1, 0, 0);
+                chunk.write_op_code(rlox::OpCode::True, location);
+                chunk.write_op_code(rlox::OpCode::JumpIfFalse, location);
+                chunk.write_byte(0, location);
+                chunk.write_byte(4, location);
+                chunk.write_op_code(rlox::OpCode::Pop, location);
+                chunk.write_op_code(rlox::OpCode::Jump, location);
+                chunk.write_byte(0, location);
+                chunk.write_byte(4, location);
+                chunk.write_op_code(rlox::OpCode::Loop, location);
+                chunk.write_byte(0, location);
+                chunk.write_byte(11, location);
+                chunk.write_op_code(rlox::OpCode::Pop, location);
+                chunk.write_op_code(rlox::OpCode::Return, location);
+            }),
+            vec![
+                "0000    1 OP_TRUE",
+                "0001    | OP_JUMP_IF_FALSE    1 -> 8",
+                "0004    | OP_POP",
+                "0005    | OP_JUMP             5 -> 12",
+                "0008    | OP_LOOP             8 -> 0",
+                "0011    | OP_POP",
+                "0012    | OP_RETURN",
+                "",
             ],
         ),
     ];
@@ -62,7 +79,7 @@ fn tests() {
         let reporter = TestReporter::new();
         rlox::disassemble_chunk(&reporter, &chunk, "test chunk");
         let mut vm = rlox::Vm::new(&reporter);
-        vm.test_interpret_chunk(chunk);
+        assert_eq!(vm.run_chunk(&chunk), rlox::InterpretResult::Ok);
         reporter.add_message(&vm.get_stack_string());
 
         if (reporter.message_count() - 1) != expected_messages.len() {
@@ -79,6 +96,689 @@ fn tests() {
     }
 }
 
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn expressions() {
+    let tests = vec![
+        ("1", "1.00"),
+        ("-1", "-1.00"),
+        ("1 + 2 * 3", "7.00"),
+        ("(1 + 2) * 3", "9.00"),
+        ("1 - 2 - 3", "-4.00"),
+        ("8 / 4 / 2", "1.00"),
+        ("-(1.2 + 3.4) / 5.6", "-0.82"),
+        ("--1", "1.00"),
+        ("true", "true"),
+        ("false", "false"),
+        ("nil", "nil"),
+        ("!true", "false"),
+        ("!nil", "true"),
+        ("!0", "false"),
+        ("1 == 1", "true"),
+        ("1 != 1", "false"),
+        ("nil == false", "false"),
+        ("nil == nil", "true"),
+        ("1 < 2", "true"),
+        ("1 <= 1", "true"),
+        ("1 > 2", "false"),
+        ("2 >= 3", "false"),
+        ("!(5 - 4 > 3 * 2 == !nil)", "true"),
+        ("\"hello\"", "hello"),
+        ("\"\"", ""),
+        ("\"st\" + \"ri\" + \"ng\"", "string"),
+        ("\"string\" == \"st\" + \"ring\"", "true"),
+        ("\"string\" != \"strinG\"", "true"),
+        ("\"1\" == 1", "false"),
+        ("0x1F + 0b1010", "41.00"),
+        ("1_000_000", "1000000.00"),
+        ("1.5e3 + 2E-2", "1500.02"),
+        ("0xFFFF_FFFF == 4_294_967_295", "true"),
+        ("0x1_0000_0000_0000_0000_0000_0000_0000_0000 == 340282366920938463463374607431768211456", "true"),
+        ("1e308 * 10 == 1e309", "true"),
+        ("!\"\"", "false"),
+        ("\"tab\\there\"", "tab\there"),
+        ("\"line\\nbreak\"", "line\nbreak"),
+        ("\"\\\"quoted\\\" \\\\ \\r\"", "\"quoted\" \\ \r"),
+        ("\"\\u{48}\\u{e9}\\u{1F600}\"", "H\u{e9}\u{1F600}"),
+        ("\"a\\\"b\" == \"a\" + \"\\\"\" + \"b\"", "true"),
+    ];
+
+    for (expression, expected_output) in tests {
+        let source = format!("print {expression};");
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.interpret(&source), rlox::InterpretResult::Ok, "Failed to interpret '{source}'");
+        assert_eq!(reporter.get_messages(), vec![expected_output], "Unexpected output for '{source}'");
+        assert_eq!(vm.get_stack_string(), "");
+    }
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn programs() {
+    let tests = vec![
+        ("", vec![]),
+        ("print 1; print 2;", vec!["1.00", "2.00"]),
+        ("1 + 2;", vec![]),
+        ("var a = 1; var b = 2; print a + b;", vec!["3.00"]),
+        ("var a; print a;", vec!["nil"]),
+        ("var a = 1; a = 3; print a;", vec!["3.00"]),
+        ("var a = 1; var a = 2; print a;", vec!["2.00"]),
+        ("var a; var b; a = b = \"c\"; print a; print b;", vec!["c", "c"]),
+        ("var breakfast = \"beignets\"; var beverage = \"cafe au lait\"; breakfast = \"beignets with \" + beverage; print breakfast;", vec!["beignets with cafe au lait"]),
+        ("{ var a = 1; print a; }", vec!["1.00"]),
+        ("var a = \"global\"; { var a = \"outer\"; { var a = \"inner\"; print a; } print a; } print a;", vec!["inner", "outer", "global"]),
+        ("{ var a = 1; { var b = a + 1; a = b * 2; } print a; }", vec!["4.00"]),
+        ("{ var a; var b = a; print b; }", vec!["nil"]),
+        ("{}", vec![]),
+        ("if (true) print 1; else print 2;", vec!["1.00"]),
+        ("if (nil) print 1; else print 2;", vec!["2.00"]),
+        ("if (false) print 1; print 3;", vec!["3.00"]),
+        ("if (1 < 2) { var a = \"yes\"; print a; }", vec!["yes"]),
+        ("print nil and 1;", vec!["nil"]),
+        ("print 1 and 2;", vec!["2.00"]),
+        ("print false or \"default\";", vec!["default"]),
+        ("print 1 or 2;", vec!["1.00"]),
+        ("print false or nil and 1;", vec!["nil"]),
+        ("var i = 0; while (i < 3) { print i; i = i + 1; }", vec!["0.00", "1.00", "2.00"]),
+        ("while (false) print 1;", vec![]),
+        ("for (var i = 0; i < 3; i = i + 1) print i;", vec!["0.00", "1.00", "2.00"]),
+        ("var i = 5; for (; i > 3;) i = i - 1; print i;", vec!["3.00"]),
+        ("var i; for (i = 0; i < 2; i = i + 1) {} print i;", vec!["2.00"]),
+        ("/* a /* nested */ comment */ print 1; // trailing", vec!["1.00"]),
+        ("var café = \"ok\"; print café;", vec!["ok"]),
+        ("var 日本 = 1; var _x2 = 日本 + 1; print _x2;", vec!["2.00"]),
+        ("/// Adds one\nfun inc(n) { return n + 1; }\nprint inc(/* inline */ 1);", vec!["2.00"]),
+        (
+            "var a = 0; var b = 1; for (var i = 0; i < 10; i = i + 1) { var t = a; a = b; b = t + b; } print a;",
+            vec!["55.00"],
+        ),
+        ("fun f() {} print f;", vec!["<fn f>"]),
+        ("fun f() {} print f();", vec!["nil"]),
+        ("fun add(a, b) { return a + b; } print add(1, 2);", vec!["3.00"]),
+        ("fun f() { print 1; return; print 2; } f();", vec!["1.00"]),
+        ("{ var a = 1; fun f(b) { var c = 3; return b + c; } print a + f(2); }", vec!["6.00"]),
+        ("fun outer() { fun inner(n) { return n * 2; } return inner; } print outer()(21);", vec!["42.00"]),
+        (
+            "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); } print fib(20);",
+            vec!["6765.00"],
+        ),
+        (
+            "fun makeCounter() { var i = 0; fun count() { i = i + 1; return i; } return count; }\n\
+             var counter = makeCounter(); counter(); print counter();",
+            vec!["2.00"],
+        ),
+        (
+            "var get; var set;\n\
+             { var a = \"initial\"; fun g() { return a; } fun s(value) { a = value; } get = g; set = s; }\n\
+             set(\"updated\"); print get();",
+            vec!["updated"],
+        ),
+        (
+            "fun outer() { var x = \"outside\"; fun middle() { fun inner() { return x; } return inner; } return middle; }\n\
+             print outer()()();",
+            vec!["outside"],
+        ),
+        (
+            "var f; { var a = 1; { var b = 2; fun g() { return a + b; } f = g; } a = 10; } print f();",
+            vec!["12.00"],
+        ),
+        (
+            "var fs; { var x = \"first\"; fun a() { return x; } x = \"second\"; fs = a; } print fs();",
+            vec!["second"],
+        ),
+        ("fun f(n) { fun g() { return n * 2; } return g(); } print f(4);", vec!["8.00"]),
+        ("class A {} print A; print A();", vec!["A", "A instance"]),
+        ("class Pair {} var p = Pair(); p.first = 1; p.second = 2; print p.first + p.second;", vec!["3.00"]),
+        ("class A {} var a = A(); print a.b = \"c\"; print a.b;", vec!["c", "c"]),
+        (
+            "class Scone { topping(a, b) { return \"scone with \" + a + \" and \" + b; } }\n\
+             print Scone().topping(\"berries\", \"cream\");",
+            vec!["scone with berries and cream"],
+        ),
+        (
+            "class Person { init(name) { this.name = name; } sayName() { print this.name; } }\n\
+             var jane = Person(\"Jane\"); var method = jane.sayName; jane.name = \"Bill\"; method(); print method;",
+            vec!["Bill", "<fn sayName>"],
+        ),
+        (
+            "class Nested { method() { fun function() { print this; } function(); } } Nested().method();",
+            vec!["Nested instance"],
+        ),
+        ("class A { init() { this.x = 1; return; } } var a = A(); print a.x; print a.init();", vec!["1.00", "A instance"]),
+        (
+            "class A { init() { fun f() { return \"field\"; } this.f = f; } f() { return \"method\"; } } print A().f();",
+            vec!["field"],
+        ),
+        (
+            "class Counter { init() { this.n = 0; } add() { this.n = this.n + 1; return this; } }\n\
+             print Counter().add().add().n;",
+            vec!["2.00"],
+        ),
+        (
+            "class Doughnut { cook() { print \"Dunk in the fryer.\"; } }\n\
+             class Cruller < Doughnut { finish() { print \"Glaze with icing.\"; } }\n\
+             var cruller = Cruller(); cruller.cook(); cruller.finish();",
+            vec!["Dunk in the fryer.", "Glaze with icing."],
+        ),
+        (
+            "class A { method() { print \"A method\"; } }\n\
+             class B < A { method() { print \"B method\"; } test() { super.method(); } }\n\
+             class C < B {}\n\
+             C().test();",
+            vec!["A method"],
+        ),
+        (
+            "class A { init(n) { this.n = n; } get() { return this.n; } }\n\
+             class B < A { init() { super.init(2); } get() { var f = super.get; return f() * 10; } }\n\
+             print B().get();",
+            vec!["20.00"],
+        ),
+        (
+            "class A { say() { return \"A\"; } }\n\
+             class B < A { say() { fun inner() { return super.say(); } return inner() + \"B\"; } }\n\
+             print B().say();",
+            vec!["AB"],
+        ),
+        ("{ class A { f() { return 1; } } class B < A {} print B().f(); }", vec!["1.00"]),
+    ];
+
+    for (source, expected_output) in tests {
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.interpret(source), rlox::InterpretResult::Ok, "Failed to interpret '{source}'");
+        assert_eq!(reporter.get_messages(), expected_output, "Unexpected output for '{source}'");
+        assert_eq!(vm.get_stack_string(), "");
+    }
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn globals_persist_between_calls() {
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.interpret("var a = \"one\";"), rlox::InterpretResult::Ok);
+    assert_eq!(vm.interpret("a = a + \" two\";"), rlox::InterpretResult::Ok);
+    assert_eq!(vm.interpret("print a;"), rlox::InterpretResult::Ok);
+    assert_eq!(reporter.get_messages(), vec!["one two"]);
+}
+
+#[test]
+fn program_runtime_errors() {
+    let tests = vec![
+        ("-true;", vec!["[0:0-1]: Operand must be a number", "[0:0-1]: in script"]),
+        ("1 + nil;", vec!["[0:2-3]: Operands must be two numbers or two strings", "[0:2-3]: in script"]),
+        ("1 >\n false;", vec!["[0:2-3]: Operands must be numbers", "[0:2-3]: in script"]),
+        ("1 + \"a\";", vec!["[0:2-3]: Operands must be two numbers or two strings", "[0:2-3]: in script"]),
+        ("\"a\" * \"b\";", vec!["[0:4-5]: Operands must be numbers", "[0:4-5]: in script"]),
+        ("-\"a\";", vec!["[0:0-1]: Operand must be a number", "[0:0-1]: in script"]),
+        ("print a;", vec!["[0:6-7]: Undefined variable 'a'", "[0:6-7]: in script"]),
+        ("var a = 1;\nb = a;", vec!["[1:0-1]: Undefined variable 'b'", "[1:0-1]: in script"]),
+        ("var a = 1; a();", vec!["[0:12-13]: Can only call functions and classes", "[0:12-13]: in script"]),
+        ("fun f(a) {} f();", vec!["[0:13-14]: Expected 1 arguments but got 0", "[0:13-14]: in script"]),
+        ("class A {} A(1);", vec!["[0:12-13]: Expected 0 arguments but got 1", "[0:12-13]: in script"]),
+        ("class A { init(a) {} } A();", vec!["[0:24-25]: Expected 1 arguments but got 0", "[0:24-25]: in script"]),
+        ("var a = 1; print a.b;", vec!["[0:19-20]: Only instances have properties", "[0:19-20]: in script"]),
+        ("var a = 1; a.b = 2;", vec!["[0:13-14]: Only instances have fields", "[0:13-14]: in script"]),
+        ("class A {} print A().b;", vec!["[0:21-22]: Undefined property 'b'", "[0:21-22]: in script"]),
+        ("class A {} A().b();", vec!["[0:15-16]: Undefined property 'b'", "[0:15-16]: in script"]),
+        ("var a = \"s\"; a.b();", vec!["[0:15-16]: Only instances have methods", "[0:15-16]: in script"]),
+        ("var A = 1; class B < A {}", vec!["[0:21-22]: Superclass must be a class", "[0:21-22]: in script"]),
+        (
+            "class A {} class B < A { f() { return super.g(); } } B().f();",
+            vec!["[0:44-45]: Undefined property 'g'", "[0:44-45]: in f()", "[0:57-58]: in script"],
+        ),
+        (
+            "class A {} class B < A { f() { return super.g; } } B().f();",
+            vec!["[0:44-45]: Undefined property 'g'", "[0:44-45]: in f()", "[0:55-56]: in script"],
+        ),
+        (
+            "class A { f() { return -this; } }\nA().f();",
+            vec!["[0:23-24]: Operand must be a number", "[0:23-24]: in f()", "[1:4-5]: in script"],
+        ),
+        (
+            "fun f() { return -nil; }\nf();",
+            vec!["[0:17-18]: Operand must be a number", "[0:17-18]: in f()", "[1:1-2]: in script"],
+        ),
+        (
+            "fun a() { b(); }\nfun b() { c(\"x\"); }\nfun c(n) { return n * 2; }\na();",
+            vec![
+                "[2:20-21]: Operands must be numbers",
+                "[2:20-21]: in c()",
+                "[1:11-12]: in b()",
+                "[0:11-12]: in a()",
+                "[3:1-2]: in script",
+            ],
+        ),
+    ];
+
+    for (source, expected_diagnostics) in tests {
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.interpret(source), rlox::InterpretResult::RuntimeError, "Expected error for '{source}'");
+        assert_eq!(reporter.get_diagnostics(), expected_diagnostics, "Unexpected diagnostics for '{source}'");
+        assert_eq!(vm.get_stack_string(), "");
+    }
+}
+
+#[test]
+fn frame_depth_limit() {
+    let source = "fun count(n) { if (n > 0) count(n - 1); } count(8);";
+
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.interpret(source), rlox::InterpretResult::Ok);
+
+    vm.set_frames_max(8);
+    assert_eq!(vm.interpret(source), rlox::InterpretResult::RuntimeError);
+    let diagnostics = reporter.get_diagnostics();
+    assert_eq!(diagnostics.len(), 9);
+    assert_eq!(diagnostics[0], "[0:31-32]: Stack overflow");
+    assert!(diagnostics[1..8].iter().all(|diagnostic| diagnostic == "[0:31-32]: in count()"));
+    assert_eq!(diagnostics[8], "[0:47-48]: in script");
+    assert_eq!(vm.get_stack_string(), "");
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn natives() {
+    fn sum<'a>(args: &[rlox::NativeValue<'a>]) -> Result<rlox::NativeValue<'a>, rlox::RuntimeError> {
+        match args {
+            [rlox::NativeValue::Number(a), rlox::NativeValue::Number(b)] => Ok(rlox::NativeValue::Number(a + b)),
+            _ => Err(rlox::RuntimeError::new("Arguments must be numbers")),
+        }
+    }
+
+    fn describe<'a>(args: &[rlox::NativeValue<'a>]) -> Result<rlox::NativeValue<'a>, rlox::RuntimeError> {
+        let description = match &args[0] {
+            rlox::NativeValue::String(chars) => format!("string {chars}"),
+            rlox::NativeValue::Object(object) => format!("object {object}"),
+            other => format!("{other:?}"),
+        };
+        Ok(rlox::NativeValue::String(description.into()))
+    }
+
+    fn first<'a>(args: &[rlox::NativeValue<'a>]) -> Result<rlox::NativeValue<'a>, rlox::RuntimeError> {
+        Ok(args[0].clone())
+    }
+
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    vm.define_native("sum", 2, sum);
+    vm.define_native("describe", 1, describe);
+    vm.define_native("first", 2, first);
+    assert_eq!(vm.interpret("print sum(1, sum(2, 3)); print sum;"), rlox::InterpretResult::Ok);
+    assert_eq!(vm.interpret("print clock() > 0; print clock;"), rlox::InterpretResult::Ok);
+    assert_eq!(
+        vm.interpret(
+            "class A {} var a = A(); print describe(\"s\" + \"t\"); print describe(a); print describe(nil);\n\
+             print first(a, 1) == a; print first(\"x\", 1) == \"x\"; print describe(1) + \"!\";"
+        ),
+        rlox::InterpretResult::Ok
+    );
+    assert_eq!(
+        reporter.get_messages(),
+        vec![
+            "6.00", "<native fn>", "true", "<native fn>", "string st", "object A instance", "Nil", "true", "true",
+            "Number(1.0)!",
+        ]
+    );
+    assert_eq!(vm.get_stack_string(), "");
+
+    assert_eq!(vm.interpret("fun f() {\n  return sum(1, nil);\n}\nf();"), rlox::InterpretResult::RuntimeError);
+    assert_eq!(vm.interpret("clock(1);"), rlox::InterpretResult::RuntimeError);
+    assert_eq!(
+        reporter.get_diagnostics(),
+        vec![
+            "[1:12-13]: Arguments must be numbers",
+            "[1:12-13]: in f()",
+            "[3:1-2]: in script",
+            "[0:5-6]: Expected 0 arguments but got 1",
+            "[0:5-6]: in script",
+        ]
+    );
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn garbage_is_collected() {
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    let source = "var kept = \"kept\";\n\
+                  for (var i = 0; i < 100000; i = i + 1) { fun garbage() { return i; } kept = kept + \"\"; }\n\
+                  print kept;";
+    assert_eq!(vm.interpret(source), rlox::InterpretResult::Ok);
+    assert_eq!(reporter.get_messages(), vec!["kept"]);
+    assert!(vm.bytes_allocated() < 2 * 1024 * 1024, "{} bytes allocated", vm.bytes_allocated());
+}
+
+#[test]
+fn undefined_global_is_not_defined_by_assignment() {
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.interpret("a = 1;"), rlox::InterpretResult::RuntimeError);
+    assert_eq!(vm.interpret("print a;"), rlox::InterpretResult::RuntimeError);
+    assert_eq!(
+        reporter.get_diagnostics(),
+        vec![
+            "[0:0-1]: Undefined variable 'a'",
+            "[0:0-1]: in script",
+            "[0:6-7]: Undefined variable 'a'",
+            "[0:6-7]: in script",
+        ]
+    );
+}
+
+#[test]
+fn compile_errors() {
+    let tests = vec![
+        "1 +;", "(1;", "1 2;", "print 1", "var = 1;", "1 = 2;", "return 1;", "fun f( {}", "f(1;",
+        "print 1; /* unterminated",
+    ];
+
+    for source in tests {
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.interpret(source), rlox::InterpretResult::CompileError, "Expected error for '{source}'");
+        assert!(reporter.has_diagnostics());
+    }
+}
+
+#[test]
+fn invalid_chunks() {
+    let tests: Vec<(TestCode, rlox::VerifyError)> = vec![
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::UnknownOpCode { offset: 0, byte: 0 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(200, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::UnknownOpCode { offset: 1, byte: 200 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Constant, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::MissingOperand {
+                offset: 0,
+                op_code: rlox::OpCode::Constant,
+            },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Constant, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(3, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::InvalidConstant { offset: 0, index: 3 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                let constant = u8::try_from(chunk.add_constant(rlox::Value::Number(1.0))).expect("constant index");
+                chunk.write_op_code(rlox::OpCode::Closure, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(constant, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::InvalidConstant { offset: 0, index: 0 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+                chunk.code.push(rlox::OpCode::Return as u8);
+            }),
+            rlox::VerifyError::MissingLocation { offset: 1 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Jump, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(1, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::InvalidJump { offset: 0, target: 4 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Nil, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::JumpIfFalse, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Loop, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(9, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::InvalidJump { offset: 4, target: -2 },
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Nil, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Loop, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(0, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_byte(1, rlox::Region::new_single_line(1, 0, 0));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 0, 0));
+            }),
+            rlox::VerifyError::InvalidJump { offset: 1, target: 3 },
+        ),
+    ];
+
+    for (closure, expected_error) in tests {
+        let mut chunk = rlox::Chunk::new();
+
+        closure(&mut chunk);
+
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(chunk.verify(), Err(expected_error.clone()));
+        assert_eq!(vm.run_chunk(&chunk), rlox::InterpretResult::InvalidChunk(expected_error));
+        assert!(reporter.has_diagnostics());
+    }
+}
+
+#[test]
+fn end_of_byte_code() {
+    let tests: Vec<(TestCode, Vec<&str>)> = vec![
+        (Box::new(|_: &mut rlox::Chunk| {}), vec!["[0:0-0]: Unexpected end of byte code", "[0:0-0]: in script"]),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_constant(rlox::Value::Number(1.2), rlox::Region::new_single_line(1, 0, 3));
+                chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::new_single_line(2, 4, 5));
+            }),
+            vec!["[2:4-5]: Unexpected end of byte code", "[2:4-5]: in script"],
+        ),
+    ];
+
+    for (closure, expected_diagnostics) in tests {
+        let mut chunk = rlox::Chunk::new();
+
+        closure(&mut chunk);
+
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.run_chunk(&chunk), rlox::InterpretResult::RuntimeError);
+        assert_eq!(reporter.get_diagnostics(), expected_diagnostics);
+        assert_eq!(vm.get_stack_string(), "");
+    }
+}
+
+#[test]
+fn runtime_errors() {
+    let tests: Vec<(TestCode, Vec<&str>)> = vec![
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::new_single_line(1, 2, 3));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 3, 4));
+            }),
+            vec!["[1:2-3]: Operand must be a number", "[1:2-3]: in script"],
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_constant(rlox::Value::Number(1.2), rlox::Region::new_single_line(1, 0, 3));
+                chunk.write_op_code(rlox::OpCode::Add, rlox::Region::new_single_line(1, 4, 5));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(1, 5, 6));
+            }),
+            vec!["[1:4-5]: Operands must be two numbers or two strings", "[1:4-5]: in script"],
+        ),
+        (
+            Box::new(|chunk: &mut rlox::Chunk| {
+                chunk.write_op_code(rlox::OpCode::Nil, rlox::Region::new_single_line(100_000, 70_001, 70_004));
+                chunk.write_op_code(rlox::OpCode::Negate, rlox::Region::new_single_line(100_000, 70_000, 70_001));
+                chunk.write_op_code(rlox::OpCode::Return, rlox::Region::new_single_line(100_000, 70_004, 70_005));
+            }),
+            vec!["[100000:70000-70001]: Operand must be a number", "[100000:70000-70001]: in script"],
+        ),
+    ];
+
+    for (closure, expected_diagnostics) in tests {
+        let mut chunk = rlox::Chunk::new();
+
+        closure(&mut chunk);
+
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.run_chunk(&chunk), rlox::InterpretResult::RuntimeError);
+        assert_eq!(reporter.get_diagnostics(), expected_diagnostics);
+        assert_eq!(vm.get_stack_string(), "");
+    }
+}
+
+#[test]
+fn huge_sources() {
+    let tests = vec![
+        (format!("{}print -nil;", "\n".repeat(70_000)), "[70000:6-7]: Operand must be a number"),
+        (format!("var a = 1;{}print -nil;", " ".repeat(70_000)), "[0:70016-70017]: Operand must be a number"),
+        (format!("var s = \"{}\";\nprint -s;", "x\n".repeat(70_000)), "[70001:6-7]: Operand must be a number"),
+    ];
+
+    for (source, expected) in tests {
+        let reporter = TestReporter::new();
+        let mut vm = rlox::Vm::new(&reporter);
+        assert_eq!(vm.interpret(&source), rlox::InterpretResult::RuntimeError);
+        assert_eq!(reporter.get_diagnostics()[0], expected);
+    }
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn long_constants() {
+    let mut chunk = rlox::Chunk::new();
+    let location = rlox::Region::new_single_line(1, 0, 0);
+    for i in 0..300 {
+        chunk.add_constant(rlox::Value::Number(i as f64));
+    }
+    assert_eq!(chunk.add_constant(rlox::Value::Number(42.0)), 42);
+    assert_eq!(chunk.add_constant(rlox::Value::Number(-0.0)), 300);
+
+    assert_eq!(chunk.write_constant(rlox::Value::Number(1.0), location), Some(1));
+    assert_eq!(chunk.write_constant(rlox::Value::Number(299.0), location), Some(299));
+    chunk.write_op_code(rlox::OpCode::Add, location);
+    chunk.write_op_code(rlox::OpCode::Print, location);
+    chunk.write_op_code(rlox::OpCode::Return, location);
+    assert_eq!(chunk.code[2..6], [rlox::OpCode::ConstantLong as u8, 0, 1, 43]);
+
+    let reporter = TestReporter::new();
+    rlox::disassemble_chunk(&reporter, &chunk, "long");
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.run_chunk(&chunk), rlox::InterpretResult::Ok);
+    assert_eq!(
+        reporter.get_messages(),
+        vec![
+            "=== long ===",
+            "0000    1 OP_CONSTANT         1 1.00",
+            "0002    | OP_CONSTANT_LONG  299 299.00",
+            "0006    | OP_ADD",
+            "0007    | OP_PRINT",
+            "0008    | OP_RETURN",
+            "300.00",
+        ]
+    );
+
+    chunk.code[4] = 2;
+    assert_eq!(chunk.verify(), Err(rlox::VerifyError::InvalidConstant { offset: 2, index: 555 }));
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn many_literals() {
+    let terms: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+    let source = format!("print {}; print 1 + 1;", terms.join(" + "));
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.interpret(&source), rlox::InterpretResult::Ok);
+    assert_eq!(reporter.get_messages(), vec!["44850.00", "2.00"]);
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn names_after_many_constants() {
+    let terms: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+    let sum = terms.join(" + ");
+    let source = format!(
+        "print {sum};
+        var a = 1;
+        fun f() {{ return a; }}
+        print f();
+        a = 2;
+        class A {{
+          init(x) {{ this.x = x; }}
+          get() {{ return this.x; }}
+        }}
+        class B < A {{
+          get() {{
+            print {sum};
+            var get = super.get;
+            return get() + super.get();
+          }}
+        }}
+        var b = B(3);
+        b.y = 4;
+        print b.get() + b.y + f();"
+    );
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.interpret(&source), rlox::InterpretResult::Ok);
+    assert_eq!(reporter.get_messages(), vec!["44850.00", "1.00", "44850.00", "12.00"]);
+}
+
+#[test]
+#[cfg_attr(any(feature = "debug_chunk", feature = "debug_trace_execution", feature = "debug_log_gc"), ignore)]
+fn loaded_chunks_run_repeatedly() {
+    let reporter = TestReporter::new();
+    let mut vm = rlox::Vm::new(&reporter);
+    assert_eq!(vm.run_loaded(), rlox::InterpretResult::RuntimeError);
+    assert_eq!(reporter.get_diagnostics(), vec!["[0:0-0]: No chunk loaded"]);
+
+    let mut chunk = rlox::Chunk::new();
+    let location = rlox::Region::new_single_line(1, 0, 0);
+    chunk.write_constant(rlox::Value::Number(1.0), location);
+    chunk.write_op_code(rlox::OpCode::Print, location);
+    chunk.write_op_code(rlox::OpCode::Return, location);
+    assert_eq!(vm.load_chunk(&chunk), Ok(()));
+    assert_eq!(vm.interpret("print 2;"), rlox::InterpretResult::Ok);
+    assert_eq!(vm.run_loaded(), rlox::InterpretResult::Ok);
+    assert_eq!(vm.run_loaded(), rlox::InterpretResult::Ok);
+    assert_eq!(reporter.get_messages(), vec!["2.00", "1.00", "1.00"]);
+
+    chunk.code[1] = 1;
+    assert_eq!(vm.load_chunk(&chunk), Err(rlox::VerifyError::InvalidConstant { offset: 0, index: 1 }));
+}
+
+#[test]
+fn op_code_round_trip() {
+    for byte in 0..=u8::MAX {
+        if let Ok(op_code) = rlox::OpCode::try_from(byte) {
+            assert_eq!(op_code as u8, byte);
+        }
+    }
+    assert!(rlox::OpCode::try_from(0).is_err());
+    assert!(rlox::OpCode::try_from(200).is_err());
+}
+
 fn display_messages(reporter: &TestReporter, expected_messages: &[&str]) {
     println!("Actual messages:");
     reporter.print_messages();
@@ -87,14 +787,14 @@ fn display_messages(reporter: &TestReporter, expected_messages: &[&str]) {
 }
 
 struct TestReporter {
-    has_errors: RefCell<bool>,
+    diagnostics: RefCell<Vec<String>>,
     messages: RefCell<Vec<String>>,
 }
 
 impl TestReporter {
     fn new() -> Self {
         Self {
-            has_errors: RefCell::new(false),
+            diagnostics: RefCell::new(Vec::new()),
             messages: RefCell::new(Vec::new()),
         }
     }
@@ -113,6 +813,14 @@ impl TestReporter {
     fn get_message(&self, i: usize) -> String {
         self.messages.borrow().get(i).unwrap().clone()
     }
+
+    fn get_messages(&self) -> Vec<String> {
+        self.messages.borrow().clone()
+    }
+
+    fn get_diagnostics(&self) -> Vec<String> {
+        self.diagnostics.borrow().clone()
+    }
 }
 
 impl rlox::Reporter for TestReporter {
@@ -120,11 +828,13 @@ impl rlox::Reporter for TestReporter {
         self.messages.borrow_mut().push(line.to_string());
     }
 
-    fn add_diagnostic(&self, _location: &rlox::Region, _message: &str) {
-        todo!()
+    fn add_diagnostic(&self, location: &rlox::Region, message: &str) {
+        self.diagnostics
+            .borrow_mut()
+            .push(format!("[{location}]: {message}"));
     }
 
     fn has_diagnostics(&self) -> bool {
-        *self.has_errors.borrow()
+        !self.diagnostics.borrow().is_empty()
     }
 }